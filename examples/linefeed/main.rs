@@ -44,8 +44,8 @@ fn main() {
                 }
             } else if let Err(err) = parser.verify() {
                 println!("{}", err);
-            } else {
-                parser.execute();
+            } else if let Err(err) = parser.try_execute() {
+                println!("{}", err);
             }
         }
         println!();
@@ -29,6 +29,17 @@
         unsafe_code, unstable_features,
         unused_import_braces, unused_qualifications)]
 
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "unicode-segmentation")]
+extern crate unicode_segmentation;
+
+#[macro_use]
+mod macros;
+
 pub mod command_table;
 pub mod menu_definition;
 pub mod parser;
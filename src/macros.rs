@@ -0,0 +1,116 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Macros for declaring command trees.
+//!
+//! These expand to the same builder calls as using [`Command`]
+//! and [`Parameter`] directly, so the two may be mixed freely.
+//!
+//! [`Command`]: parser/struct.Command.html
+//! [`Parameter`]: parser/struct.Parameter.html
+
+/// Declare a [`Command`].
+///
+/// The name is followed by `key = value` pairs, each of which
+/// calls the builder method `key` with `value`. The keys
+/// `params` and `subcommands` take a list, adding each item with
+/// `parameter` or `subcommand`.
+///
+/// ```
+/// #[macro_use]
+/// extern crate commands;
+///
+/// # fn main() {
+/// let root = command_tree![
+///     command!("show", help = "Show an item.", params = [flag!("--all"), parameter!("item")]),
+///     command!("quit", hidden = true),
+/// ];
+/// # let _ = root;
+/// # }
+/// ```
+///
+/// The macros may also be imported by path:
+///
+/// ```edition2018
+/// use commands::{command, command_tree, flag};
+///
+/// let root = command_tree![command!("show", params = [flag!("--all")])];
+/// # let _ = root;
+/// ```
+///
+/// [`Command`]: parser/struct.Command.html
+#[macro_export]
+macro_rules! command {
+    ($name:expr) => {
+        $crate::parser::Command::new($name)
+    };
+    ($name:expr, $($rest:tt)*) => {
+        $crate::__commands_build!($crate::parser::Command::new($name), $($rest)*)
+    };
+}
+
+/// Declare a [`Parameter`].
+///
+/// This takes `key = value` pairs in the same way as
+/// [`command!`](macro.command.html).
+///
+/// [`Parameter`]: parser/struct.Parameter.html
+#[macro_export]
+macro_rules! parameter {
+    ($name:expr) => {
+        $crate::parser::Parameter::new($name)
+    };
+    ($name:expr, $($rest:tt)*) => {
+        $crate::__commands_build!($crate::parser::Parameter::new($name), $($rest)*)
+    };
+}
+
+/// Declare a [`Parameter`] which is a flag.
+///
+/// This takes `key = value` pairs in the same way as
+/// [`command!`](macro.command.html).
+///
+/// [`Parameter`]: parser/struct.Parameter.html
+#[macro_export]
+macro_rules! flag {
+    ($name:expr) => {
+        $crate::parameter!($name, kind = $crate::parser::ParameterKind::Flag)
+    };
+    ($name:expr, $($rest:tt)*) => {
+        $crate::parameter!($name, kind = $crate::parser::ParameterKind::Flag, $($rest)*)
+    };
+}
+
+/// Declare a command tree from some commands, finalizing it to
+/// give the root node.
+#[macro_export]
+macro_rules! command_tree {
+    ($($command:expr),* $(,)*) => {{
+        let mut tree = $crate::parser::CommandTree::new();
+        $(tree.command($command);)*
+        tree.finalize()
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __commands_build {
+    ($builder:expr $(,)*) => {
+        $builder
+    };
+    ($builder:expr, params = [$($param:expr),* $(,)*] $($rest:tt)*) => {
+        $crate::__commands_build!($builder$(.parameter($param))* $($rest)*)
+    };
+    ($builder:expr, subcommands = [$($command:expr),* $(,)*] $($rest:tt)*) => {
+        $crate::__commands_build!($builder$(.subcommand($command))* $($rest)*)
+    };
+    ($builder:expr, $key:ident = $value:expr) => {
+        $builder.$key($value)
+    };
+    ($builder:expr, $key:ident = $value:expr, $($rest:tt)*) => {
+        $crate::__commands_build!($builder.$key($value), $($rest)*)
+    };
+}
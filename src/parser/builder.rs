@@ -4,9 +4,14 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::rc::Rc;
+use super::{CommandHandler, ExecStatus, Parser, ValueCompleter};
 use super::constants::*;
 use super::nodes::*;
+use super::validation::{PathValidation, ValueType};
 
 /// Store a command tree while populating it. This is used
 /// to construct a [`RootNode`] to be used with the [`Parser`].
@@ -21,11 +26,35 @@ use super::nodes::*;
 /// [`RootNode`]: struct.RootNode.html
 pub struct CommandTree<'a> {
     commands: Vec<Command<'a>>,
+    path_aliases: Vec<(&'a str, Vec<&'a str>)>,
+    default_priority: i32,
+    unknown_command_message: Option<UnknownCommandMessage>,
+    lazy_commands: Vec<(&'a str, CommandFactory)>,
 }
 
+impl<'a> fmt::Debug for CommandTree<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("CommandTree")
+            .field("commands", &self.commands)
+            .field("lazy_commands", &self.lazy_commands.iter().map(|c| c.0).collect::<Vec<_>>())
+            .field("path_aliases", &self.path_aliases)
+            .field("default_priority", &self.default_priority)
+            .finish()
+    }
+}
+
+/// Constructs the definition of a lazily defined command.
+type CommandFactory = Rc<dyn Fn() -> Command<'static>>;
+
 impl<'a> Default for CommandTree<'a> {
     fn default() -> Self {
-        CommandTree { commands: vec![] }
+        CommandTree {
+            commands: vec![],
+            path_aliases: vec![],
+            default_priority: PRIORITY_DEFAULT,
+            unknown_command_message: None,
+            lazy_commands: vec![],
+        }
     }
 }
 
@@ -35,56 +64,413 @@ impl<'a> CommandTree<'a> {
         Default::default()
     }
 
+    /// Create a new `CommandTree` containing the `commands`.
+    ///
+    /// This is useful with commands which have been deserialized,
+    /// which requires the `serde` feature.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree};
+    ///
+    /// let tree = CommandTree::from_commands(vec![Command::new("show"), Command::new("set")]);
+    /// ```
+    pub fn from_commands(commands: Vec<Command<'a>>) -> Self {
+        CommandTree {
+            commands: commands,
+            ..Default::default()
+        }
+    }
+
     /// Add a `Command` to the `CommandTree`.
     pub fn command(&mut self, command: Command<'a>) {
         self.commands.push(command);
     }
 
+    /// Add the commands of `other` to the `CommandTree`, such as
+    /// to assemble a tree from commands provided by several modules.
+    ///
+    /// Commands from `other` which don't specify their own priority
+    /// keep the default priority of `other`. Names which are given
+    /// by both trees are reported by `try_finalize` in the same way
+    /// as names given twice within a tree.
+    ///
+    /// ```
+    /// use commands::parser::{BuildError, Command, CommandTree};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// let mut plugin = CommandTree::new();
+    /// plugin.command(Command::new("show"));
+    /// tree.extend(plugin);
+    /// assert_eq!(tree.try_finalize().unwrap_err(), BuildError::DuplicateCommand("show".into()));
+    /// ```
+    pub fn extend(&mut self, other: CommandTree<'a>) {
+        let default_priority = other.default_priority;
+        self.commands.extend(other.commands.into_iter().map(|mut command| {
+            command.priority = command.priority.or(Some(default_priority));
+            command
+        }));
+        self.lazy_commands.extend(other.lazy_commands.into_iter().map(|(name, factory)| {
+            let factory: CommandFactory = Rc::new(move || {
+                let mut command = factory();
+                command.priority = command.priority.or(Some(default_priority));
+                command
+            });
+            (name, factory)
+        }));
+        self.path_aliases.extend(other.path_aliases);
+        if self.unknown_command_message.is_none() {
+            self.unknown_command_message = other.unknown_command_message;
+        }
+    }
+
+    /// Add a command named `name` whose definition is only
+    /// constructed by the `factory` when it is first matched or
+    /// completed. This avoids the cost of building commands which
+    /// are never used.
+    ///
+    /// The `factory` is only run once, after which the definition
+    /// is reused. It should return a `Command` named `name`. Any
+    /// problems with the definition which would otherwise be given
+    /// as warnings by `finalize` are not recorded.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.lazy_command("show", Box::new(|| {
+    ///     Command::new("show").parameter(Parameter::new("item"))
+    /// }));
+    /// ```
+    pub fn lazy_command(&mut self, name: &'a str, factory: Box<dyn Fn() -> Command<'static>>) {
+        self.lazy_commands.push((name, Rc::from(factory)));
+    }
+
+    /// Set the priority given to commands in this tree which
+    /// don't specify their own priority.
+    ///
+    /// This allows the commands from one tree, such as those
+    /// provided by a plugin, to be shifted relative to others.
+    /// This defaults to `PRIORITY_DEFAULT`.
+    pub fn default_priority(&mut self, priority: i32) {
+        self.default_priority = priority;
+    }
+
+    /// Supply the message given when the first word of a command
+    /// line doesn't match any command.
+    ///
+    /// The `message` is given the unmatched text, and is available
+    /// for the resulting `ParseError::NoMatches` from
+    /// `Parser::unknown_command_message`.
+    ///
+    /// ```
+    /// use commands::parser::CommandTree;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.unknown_command_message(Box::new(|text| {
+    ///     format!("Unknown command '{}'. Type 'help'.", text)
+    /// }));
+    /// ```
+    pub fn unknown_command_message(&mut self, message: Box<dyn Fn(&str) -> String>) {
+        self.unknown_command_message = Some(Rc::from(message));
+    }
+
+    /// Add an alias which expands to a whole command path.
+    ///
+    /// When the `alias` is given as the first word of a command
+    /// line, the parser treats it as though each word of the
+    /// `target_path` had been given instead.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, ParameterKind};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show")
+    ///                  .parameter(Parameter::new("interface")
+    ///                                 .kind(ParameterKind::Flag)));
+    /// tree.path_alias("sh-int", &["show", "interface"]);
+    /// ```
+    pub fn path_alias(&mut self, alias: &'a str, target_path: &[&'a str]) {
+        self.path_aliases.push((alias, target_path.to_vec()));
+    }
+
+    /// Compare the commands of this tree with those of `other`.
+    ///
+    /// Commands and parameters are matched by name. The resulting
+    /// [`TreeDiff`] gives the commands which are only in `other` as
+    /// added and those which are only in this tree as removed.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree};
+    ///
+    /// let mut before = CommandTree::new();
+    /// before.command(Command::new("show"));
+    ///
+    /// let mut after = CommandTree::new();
+    /// after.command(Command::new("show"));
+    /// after.command(Command::new("set"));
+    ///
+    /// let diff = before.diff(&after);
+    /// assert_eq!(diff.added, vec!["set"]);
+    /// assert!(diff.removed.is_empty());
+    /// ```
+    ///
+    /// [`TreeDiff`]: struct.TreeDiff.html
+    pub fn diff(&self, other: &CommandTree) -> TreeDiff {
+        let mut diff = TreeDiff::default();
+        for command in &self.commands {
+            match other.commands.iter().find(|c| c.name == command.name) {
+                Some(other) => {
+                    let changes = command.diff(other);
+                    if changes.is_changed() {
+                        diff.changed.push(changes);
+                    }
+                }
+                None => diff.removed.push(command.name.to_string()),
+            }
+        }
+        for command in &other.commands {
+            if !self.commands.iter().any(|c| c.name == command.name) {
+                diff.added.push(command.name.to_string());
+            }
+        }
+        diff
+    }
+
+    /// Construct the `CommandTree` and produce a `RootNode`,
+    /// failing if the tree can't be parsed unambiguously.
+    ///
+    /// This is preferred over `finalize`. It fails with
+    /// `BuildError::DuplicateCommand` when a name or path alias
+    /// is given to more than one command which isn't hidden,
+    /// counting the aliases of commands as names, whether at the
+    /// top of the tree or among the subcommands of a command. It
+    /// fails with `BuildError::ShortAliasUnavailable` when the
+    /// automatic short alias of a command or parameter is already
+    /// in use, and with `BuildError::UnresolvedWrap` when the path given to
+    /// `Command::wraps` doesn't lead to a command. It fails with
+    /// `BuildError::UnknownParameter` when a parameter requires or
+    /// conflicts with a parameter that its command doesn't have,
+    /// and with `BuildError::DuplicateParameter` when a name or
+    /// alias is given to more than one parameter of a command. It
+    /// fails with `BuildError::SlotConflict` when a choice fills a
+    /// slot which has the name of a parameter of its command, and
+    /// with `BuildError::GreedyNotSimple` when `Parameter::greedy` is
+    /// given to a parameter which isn't `ParameterKind::Simple`.
+    ///
+    /// ```
+    /// use commands::parser::{BuildError, Command, CommandTree};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("show"));
+    /// match tree.try_finalize() {
+    ///     Err(BuildError::DuplicateCommand(name)) => assert_eq!(name, "show"),
+    ///     _ => panic!("expected a duplicate command"),
+    /// }
+    /// ```
+    pub fn try_finalize(&self) -> Result<Rc<Node>, BuildError> {
+        let mut errors = vec![];
+        check_command_names(&self.commands, self.reserved_names(), &mut vec![], &mut errors);
+        for command in &self.commands {
+            check_parameter_names(command, &mut errors);
+            check_dependencies(command, &mut errors);
+            check_greedy(command, &mut errors);
+        }
+        let root = self.build(&mut errors);
+        if let Node::Root(ref root) = *root {
+            for warning in &root.warnings {
+                if let BuildWarning::ShortAliasUnavailable(ref name, ref alias) = *warning {
+                    errors.push(BuildError::ShortAliasUnavailable(name.clone(), alias.clone()));
+                }
+            }
+        }
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(root),
+        }
+    }
+
     /// Construct the `CommandTree` and produce a `RootNode`.
+    ///
+    /// Any problems which don't prevent the tree from being built
+    /// are recorded in the `warnings` of the `RootNode`. Commands
+    /// which share a name are not detected and a command which
+    /// wraps a path that doesn't lead to a command wraps nothing,
+    /// so `try_finalize` should be preferred.
     pub fn finalize(&self) -> Rc<Node> {
+        self.build(&mut vec![])
+    }
+
+    /// The names at the top of the tree which aren't the names
+    /// or aliases of its commands: those of lazy commands and
+    /// path aliases.
+    fn reserved_names(&self) -> Vec<&'a str> {
+        self.lazy_commands
+            .iter()
+            .map(|&(name, _)| name)
+            .chain(self.path_aliases.iter().map(|&(alias, _)| alias))
+            .collect()
+    }
+
+    fn build(&self, errors: &mut Vec<BuildError>) -> Rc<Node> {
+        let mut warnings = vec![];
+        let siblings = self.commands
+            .iter()
+            .map(|c| (c.name, command_alias_names(c), c.auto_short_alias))
+            .collect::<Vec<_>>();
+        let short_aliases = short_aliases(&siblings, &self.reserved_names(), &mut warnings);
         let mut successors: Vec<Rc<Node>> = vec![];
-        for c in &self.commands {
-            successors.push(Rc::new(Node::Command(self.build_command(c))));
+        let mut wrappers = vec![];
+        for (c, alias) in self.commands.iter().zip(short_aliases) {
+            let command = self.build_command(c, alias, &mut warnings);
+            match c.wrapped_root {
+                Some(ref path) => wrappers.push((successors.len(), command, path)),
+                None => successors.push(Rc::new(Node::Command(command))),
+            }
+        }
+        // Wrappers are resolved once the commands that they may
+        // wrap have been built.
+        let mut wrapped = vec![];
+        for (index, mut command, path) in wrappers {
+            if path.trim().is_empty() {
+                command.node.successors.extend(successors.iter().cloned());
+            } else {
+                match resolve_path(&successors, path) {
+                    Some(target) => {
+                        command.node.successors.extend(target.successors().iter().cloned());
+                        if let Node::Command(ref target) = *target {
+                            command.parameters.extend(target.parameters.iter().cloned());
+                        }
+                        command.wrapped_root = Some(target);
+                    }
+                    None => errors.push(BuildError::UnresolvedWrap(
+                        command.node.name.clone(),
+                        path.clone(),
+                    )),
+                }
+            }
+            wrapped.push((index, Rc::new(Node::Command(command))));
+        }
+        for (offset, (index, command)) in wrapped.into_iter().enumerate() {
+            successors.insert(index + offset, command);
+        }
+        for &(name, ref factory) in &self.lazy_commands {
+            let factory = Rc::clone(factory);
+            let default_priority = self.default_priority;
+            let build = move || {
+                let mut tree = CommandTree::new();
+                tree.default_priority = default_priority;
+                tree.build_command(&factory(), None, &mut vec![])
+            };
+            let mut command = CommandNode::new(
+                name,
+                None,
+                false,
+                self.default_priority,
+                vec![],
+                None,
+                vec![],
+                &[],
+            );
+            command.lazy = Some(LazyDefinition::new(Box::new(build)));
+            successors.push(Rc::new(Node::Command(command)));
         }
-        Rc::new(Node::Root(RootNode::new(successors)))
+        let mut root = RootNode::new(successors);
+        root.warnings = warnings;
+        root.unknown_command_message = self.unknown_command_message.clone();
+        root.path_aliases = self.path_aliases
+            .iter()
+            .map(|&(alias, ref path)| {
+                (
+                    alias.to_string(),
+                    path.iter().map(|p| p.to_string()).collect(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        root.node.index_successors();
+        Rc::new(Node::Root(root))
     }
 
-    fn build_command(&self, command: &Command) -> CommandNode {
+    fn build_command(
+        &self,
+        command: &Command,
+        short_alias: Option<String>,
+        warnings: &mut Vec<BuildWarning>,
+    ) -> CommandNode {
         let mut parameters: Vec<Rc<Node>> = vec![];
         let mut successors: Vec<Rc<Node>> = vec![];
-        for parameter in &command.parameters {
+        let siblings = command
+            .parameters
+            .iter()
+            .map(|p| (p.name, p.alias_names(), p.auto_short_alias))
+            .collect::<Vec<_>>();
+        let parameter_aliases = short_aliases(&siblings, &[], warnings);
+        for (parameter, alias) in command.parameters.iter().zip(parameter_aliases) {
             match parameter.kind {
                 ParameterKind::Flag => {
-                    self.build_flag_parameter(parameter, &mut parameters, &mut successors);
+                    self.build_flag_parameter(parameter, alias, &mut parameters, &mut successors);
                 }
                 ParameterKind::Named => {
-                    self.build_named_parameter(parameter, &mut parameters, &mut successors);
+                    self.build_named_parameter(parameter, alias, &mut parameters, &mut successors);
                 }
                 ParameterKind::Simple => {
                     self.build_simple_parameter(parameter, &mut parameters, &mut successors);
                 }
             };
         }
+        if let Some(token) = command.required_token {
+            let parameter = Parameter::new(token).kind(ParameterKind::Flag);
+            self.build_flag_parameter(&parameter, None, &mut parameters, &mut successors);
+        }
+        let siblings = command
+            .subcommands
+            .iter()
+            .map(|c| (c.name, command_alias_names(c), c.auto_short_alias))
+            .collect::<Vec<_>>();
+        let short_aliases = short_aliases(&siblings, &[], warnings);
+        for (subcommand, alias) in command.subcommands.iter().zip(short_aliases) {
+            let subcommand = self.build_command(subcommand, alias, warnings);
+            successors.push(Rc::new(Node::Command(subcommand)));
+        }
         // We'll want to find the right node for the wrapped_root
         // and pass it along here.
-        CommandNode::new(
+        let mut c = CommandNode::new(
             command.name,
             command.help_text,
             command.hidden,
-            command.priority,
+            command.priority.unwrap_or(self.default_priority),
             successors,
-            None,
+            command.handler.clone(),
             parameters,
-        )
+            &command.see_also,
+        );
+        c.node.aliases.extend(command.aliases.iter().map(|a| a.to_string()));
+        c.node.aliases.extend(short_alias);
+        c.node.deprecated = command.deprecated;
+        if let Some(symbol) = command.help_symbol {
+            c.node.help_symbol = symbol.to_string();
+        }
+        c.help_header = command.help_header.map(|h| h.to_string());
+        c.help_footer = command.help_footer.map(|f| f.to_string());
+        c.required_token = command.required_token.map(|t| t.to_string());
+        c.exclusive_groups = command
+            .exclusive_groups
+            .iter()
+            .map(|g| g.iter().map(|p| p.to_string()).collect())
+            .collect();
+        lint_command(command, warnings);
+        c
     }
 
     fn build_flag_parameter(
         &self,
         parameter: &Parameter,
+        short_alias: Option<String>,
         parameters: &mut Vec<Rc<Node>>,
         successors: &mut Vec<Rc<Node>>,
     ) {
-        let p = ParameterNode::new(
+        let mut p = ParameterNode::new(
             parameter.name,
             parameter.help_text,
             parameter.hidden,
@@ -95,18 +481,42 @@ impl<'a> CommandTree<'a> {
             parameter.kind,
             parameter.required,
         );
+        p.node.aliases.extend(parameter.alias_names());
+        p.node.aliases.extend(short_alias);
+        apply_common(&mut p, parameter);
         let p = Rc::new(Node::Parameter(p));
         parameters.push(Rc::clone(&p));
         successors.push(p);
+        if parameter.negatable {
+            let name = negated_name(parameter.name);
+            let mut n = ParameterNode::new(
+                &name,
+                parameter.help_text,
+                parameter.hidden,
+                parameter.priority.unwrap_or(PRIORITY_DEFAULT),
+                vec![],
+                false,
+                None,
+                ParameterKind::Flag,
+                false,
+            );
+            n.node.deprecated = parameter.deprecated;
+            n.experimental = parameter.experimental;
+            n.negates = Some(parameter.name.to_string());
+            let n = Rc::new(Node::Parameter(n));
+            parameters.push(Rc::clone(&n));
+            successors.push(n);
+        }
     }
 
     fn build_named_parameter(
         &self,
         parameter: &Parameter,
+        short_alias: Option<String>,
         parameters: &mut Vec<Rc<Node>>,
         successors: &mut Vec<Rc<Node>>,
     ) {
-        let p = ParameterNode::new(
+        let mut p = ParameterNode::new(
             parameter.name,
             parameter.help_text,
             parameter.hidden,
@@ -117,9 +527,10 @@ impl<'a> CommandTree<'a> {
             parameter.kind,
             parameter.required,
         );
+        apply_common(&mut p, parameter);
         let p = Rc::new(Node::Parameter(p));
         parameters.push(Rc::clone(&p));
-        let n = ParameterNameNode::new(
+        let mut n = ParameterNameNode::new(
             parameter.name,
             parameter.hidden,
             PRIORITY_DEFAULT,
@@ -128,19 +539,12 @@ impl<'a> CommandTree<'a> {
             Some(Rc::clone(&p)),
             Rc::clone(&p),
         );
-        successors.push(Rc::new(Node::ParameterName(n)));
-        for alias in &parameter.aliases {
-            let a = ParameterNameNode::new(
-                alias,
-                parameter.hidden,
-                PRIORITY_DEFAULT,
-                vec![Rc::clone(&p)],
-                parameter.repeatable,
-                Some(Rc::clone(&p)),
-                Rc::clone(&p),
-            );
-            successors.push(Rc::new(Node::ParameterName(a)));
+        if let Some(symbol) = parameter.help_symbol {
+            n.node.help_symbol = symbol.to_string();
         }
+        n.node.aliases.extend(parameter.alias_names());
+        n.node.aliases.extend(short_alias);
+        successors.push(Rc::new(Node::ParameterName(n)));
     }
 
     fn build_simple_parameter(
@@ -149,7 +553,7 @@ impl<'a> CommandTree<'a> {
         parameters: &mut Vec<Rc<Node>>,
         successors: &mut Vec<Rc<Node>>,
     ) {
-        let p = ParameterNode::new(
+        let mut p = ParameterNode::new(
             parameter.name,
             parameter.help_text,
             parameter.hidden,
@@ -160,25 +564,456 @@ impl<'a> CommandTree<'a> {
             parameter.kind,
             parameter.required,
         );
+        apply_common(&mut p, parameter);
         let p = Rc::new(Node::Parameter(p));
         parameters.push(Rc::clone(&p));
         successors.push(Rc::clone(&p));
     }
 }
 
+/// Copy the settings of `parameter` which apply to its kind to
+/// the node `p` built for it.
+fn apply_common(p: &mut ParameterNode, parameter: &Parameter) {
+    p.node.deprecated = parameter.deprecated;
+    p.experimental = parameter.experimental;
+    p.slot = parameter.slot.map(|s| s.to_string());
+    p.default_value = parameter.default_value.map(|v| v.to_string());
+    p.min_occurs = parameter.min_occurs;
+    p.max_occurs = parameter.max_occurs;
+    p.requires = parameter.requires.iter().map(|r| r.to_string()).collect();
+    p.conflicts_with = parameter.conflicts_with.iter().map(|c| c.to_string()).collect();
+    if let Some(symbol) = parameter.help_symbol {
+        p.node.help_symbol = symbol.to_string();
+    }
+    if parameter.kind != ParameterKind::Flag {
+        p.choices = parameter.choices.clone();
+        p.completer = parameter.completer.clone();
+        p.list_separator = parameter.list_separator;
+        p.path_validation = parameter.path_validation;
+        p.value_type = parameter.value_type;
+        p.context_key = parameter.context_key.map(|k| k.to_string());
+    }
+    match parameter.kind {
+        ParameterKind::Flag => p.negatable = parameter.negatable,
+        ParameterKind::Named => p.value_optional = parameter.value_optional,
+        ParameterKind::Simple => p.greedy = parameter.greedy,
+    }
+}
+
+/// The name of the negation of the flag `name`, which is `--no-color`
+/// for `--color`.
+fn negated_name(name: &str) -> String {
+    let letters = name.trim_start_matches('-');
+    format!("{}no-{}", &name[..name.len() - letters.len()], letters)
+}
+
+/// Find the command at the end of `path`, a list of command names
+/// separated by whitespace, starting from `commands`.
+fn resolve_path(commands: &[Rc<Node>], path: &str) -> Option<Rc<Node>> {
+    let mut candidates = commands;
+    let mut command = None;
+    for name in path.split_whitespace() {
+        let node = candidates.iter().find(|n| match ***n {
+            Node::Command(ref c) => c.node.is_named(name),
+            _ => false,
+        })?;
+        let node = match **node {
+            Node::Command(CommandNode { lazy: Some(ref lazy), .. }) => lazy.definition(),
+            _ => node,
+        };
+        candidates = node.successors();
+        command = Some(Rc::clone(node));
+    }
+    command
+}
+
+/// Derive single-character aliases for those `siblings` which
+/// want one, recording a warning for each that collides with
+/// the name or alias of another sibling.
+///
+/// Each sibling is given as its name, its aliases and whether
+/// it wants a short alias.
+fn short_aliases(
+    siblings: &[(&str, Vec<String>, bool)],
+    reserved: &[&str],
+    warnings: &mut Vec<BuildWarning>,
+) -> Vec<Option<String>> {
+    let mut taken = siblings
+        .iter()
+        .flat_map(|&(name, ref aliases, _)| {
+            Some(name.to_string()).into_iter().chain(aliases.clone())
+        })
+        .chain(reserved.iter().map(|n| n.to_string()))
+        .collect::<Vec<_>>();
+    let mut short_aliases = vec![];
+    for &(name, _, wanted) in siblings {
+        let alias = if wanted { short_alias_for(name) } else { None };
+        let alias = match alias {
+            Some(ref alias) if taken.contains(alias) => {
+                warnings.push(BuildWarning::ShortAliasUnavailable(
+                    name.to_string(),
+                    alias.clone(),
+                ));
+                None
+            }
+            Some(alias) => {
+                taken.push(alias.clone());
+                Some(alias)
+            }
+            None => None,
+        };
+        short_aliases.push(alias);
+    }
+    short_aliases
+}
+
+/// The short alias for `name` is its first letter, keeping a
+/// single leading `-` if the name is an option like `--verbose`.
+fn short_alias_for(name: &str) -> Option<String> {
+    let letters = name.trim_start_matches('-');
+    let first = letters.chars().next()?;
+    let mut alias = if letters.len() < name.len() {
+        String::from("-")
+    } else {
+        String::new()
+    };
+    alias.push(first);
+    if alias == name { None } else { Some(alias) }
+}
+
+/// The differences between two [`CommandTree`]s, as found by
+/// `CommandTree::diff`.
+///
+/// [`CommandTree`]: struct.CommandTree.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeDiff {
+    /// The names of commands which were added.
+    pub added: Vec<String>,
+    /// The names of commands which were removed.
+    pub removed: Vec<String>,
+    /// The commands which are in both trees, but differ.
+    pub changed: Vec<CommandDiff>,
+}
+
+impl TreeDiff {
+    /// Whether or not the trees have the same commands.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences in a command which is in both trees
+/// given to `CommandTree::diff`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandDiff {
+    /// The name of the command.
+    pub name: String,
+    /// Whether the command itself changed, such as its help text
+    /// or whether it is hidden.
+    pub changed: bool,
+    /// The names of parameters which were added.
+    pub added_parameters: Vec<String>,
+    /// The names of parameters which were removed.
+    pub removed_parameters: Vec<String>,
+    /// The names of parameters which are in both commands, but
+    /// differ, such as in their kind or requiredness.
+    pub changed_parameters: Vec<String>,
+}
+
+impl CommandDiff {
+    fn is_changed(&self) -> bool {
+        self.changed || !self.added_parameters.is_empty() ||
+            !self.removed_parameters.is_empty() ||
+            !self.changed_parameters.is_empty()
+    }
+}
+
+/// The aliases of a `command`, as names which its siblings may
+/// not use.
+fn command_alias_names(command: &Command) -> Vec<String> {
+    command.aliases.iter().map(|a| a.to_string()).collect()
+}
+
+/// Check that no name or alias is given to more than one of the
+/// `commands` which aren't hidden, or to one of them and one of the
+/// `reserved` names, and likewise for the subcommands of each
+/// command. The `path` holds the names of the commands leading to
+/// the `commands`.
+fn check_command_names<'a>(
+    commands: &[Command<'a>],
+    reserved: Vec<&'a str>,
+    path: &mut Vec<&'a str>,
+    errors: &mut Vec<BuildError>,
+) {
+    let names = commands
+        .iter()
+        .filter(|c| !c.hidden)
+        .flat_map(|c| Some(c.name).into_iter().chain(c.aliases.iter().cloned()))
+        .chain(reserved);
+    let mut seen = vec![];
+    for name in names {
+        if seen.contains(&name) {
+            path.push(name);
+            errors.push(BuildError::DuplicateCommand(path.join(" ")));
+            path.pop();
+            return;
+        }
+        seen.push(name);
+    }
+    for command in commands {
+        path.push(command.name);
+        check_command_names(&command.subcommands, vec![], path, errors);
+        path.pop();
+    }
+}
+
+/// Check that no name or alias is given to more than one of the
+/// parameters of a `command` or of its subcommands, or to a slot
+/// of one of their choices.
+fn check_parameter_names(command: &Command, errors: &mut Vec<BuildError>) {
+    let mut seen = vec![];
+    for parameter in &command.parameters {
+        for name in Some(parameter.name.to_string()).into_iter().chain(parameter.alias_names()) {
+            if seen.contains(&name) {
+                errors.push(BuildError::DuplicateParameter(command.name.to_string(), name));
+                return;
+            }
+            seen.push(name);
+        }
+    }
+    for slot in command.parameters.iter().filter_map(|p| p.slot) {
+        if seen.iter().any(|name| name == slot) {
+            errors.push(BuildError::SlotConflict(command.name.to_string(), slot.to_string()));
+            return;
+        }
+    }
+    for subcommand in &command.subcommands {
+        check_parameter_names(subcommand, errors);
+    }
+}
+
+/// Check that the parameters which the parameters of a `command`
+/// and its subcommands require or conflict with exist.
+fn check_dependencies(command: &Command, errors: &mut Vec<BuildError>) {
+    for parameter in &command.parameters {
+        for &target in parameter.requires.iter().chain(&parameter.conflicts_with) {
+            if !command.parameters.iter().any(|p| p.name == target) {
+                errors.push(BuildError::UnknownParameter(
+                    parameter.name.to_string(),
+                    target.to_string(),
+                ));
+            }
+        }
+    }
+    for subcommand in &command.subcommands {
+        check_dependencies(subcommand, errors);
+    }
+}
+
+/// Check that only simple parameters of a `command` and its
+/// subcommands are greedy.
+fn check_greedy(command: &Command, errors: &mut Vec<BuildError>) {
+    for parameter in &command.parameters {
+        if parameter.greedy && parameter.kind != ParameterKind::Simple {
+            errors.push(BuildError::GreedyNotSimple(
+                command.name.to_string(),
+                parameter.name.to_string(),
+            ));
+        }
+    }
+    for subcommand in &command.subcommands {
+        check_greedy(subcommand, errors);
+    }
+}
+
+/// Check that the required parameters of a `command` can all be
+/// given, recording a warning for those which conflict.
+fn lint_command(command: &Command, warnings: &mut Vec<BuildWarning>) {
+    for group in &command.exclusive_groups {
+        let required = group
+            .iter()
+            .filter(|&&name| {
+                command.parameters.iter().any(|p| p.name == name && p.required)
+            })
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        if required.len() > 1 {
+            warnings.push(BuildWarning::UnsatisfiableCommand(
+                command.name.to_string(),
+                required,
+            ));
+        }
+    }
+}
+
+/// A problem found while building a [`CommandTree`] which does
+/// not prevent the tree from being built.
+///
+/// [`CommandTree`]: struct.CommandTree.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildWarning {
+    /// The automatic short alias for a node was already in use
+    /// by a sibling, so the node was given no short alias. This
+    /// holds the name of the node and the alias.
+    ShortAliasUnavailable(String, String),
+    /// The required parameters of a command can never all be
+    /// given, so the command can never be executed. This holds
+    /// the name of the command and of the conflicting parameters.
+    UnsatisfiableCommand(String, Vec<String>),
+}
+
+impl fmt::Display for BuildWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            BuildWarning::ShortAliasUnavailable(ref name, ref alias) => {
+                write!(f, "The short alias '{}' for '{}' is already in use.", alias, name)
+            }
+            BuildWarning::UnsatisfiableCommand(ref name, ref parameters) => {
+                write!(
+                    f,
+                    "The command '{}' can not be given all of '{}'.",
+                    name,
+                    parameters.join("', '")
+                )
+            }
+        }
+    }
+}
+
+/// Problems which prevent a [`CommandTree`] from being built.
+///
+/// [`CommandTree`]: struct.CommandTree.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    /// More than one command was given this name, so it
+    /// could never be matched unambiguously. For subcommands,
+    /// this holds the names of the commands leading to the
+    /// name as well, as in `show interface`.
+    DuplicateCommand(String),
+    /// The automatic short alias for a command or parameter was
+    /// already in use by a sibling. This holds the name of the
+    /// node and the alias.
+    ShortAliasUnavailable(String, String),
+    /// The path wrapped by a command doesn't lead to a command.
+    /// This holds the name of the command and the path.
+    UnresolvedWrap(String, String),
+    /// A parameter requires or conflicts with a parameter which
+    /// its command doesn't have. This holds the names of the
+    /// parameter and of the missing parameter.
+    UnknownParameter(String, String),
+    /// More than one parameter of a command was given this name,
+    /// counting aliases as names. This holds the names of the
+    /// command and of the parameter.
+    DuplicateParameter(String, String),
+    /// A choice of a command fills a slot which has the name of
+    /// one of its parameters. This holds the names of the command
+    /// and of the slot.
+    SlotConflict(String, String),
+    /// A parameter which isn't `ParameterKind::Simple` was made
+    /// greedy. This holds the names of the command and of the
+    /// parameter.
+    GreedyNotSimple(String, String),
+}
+
+impl Error for BuildError {}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            BuildError::DuplicateCommand(ref name) => {
+                write!(f, "The command '{}' is defined more than once.", name)
+            }
+            BuildError::ShortAliasUnavailable(ref name, ref alias) => {
+                write!(f, "The short alias '{}' for '{}' is already in use.", alias, name)
+            }
+            BuildError::UnresolvedWrap(ref name, ref path) => {
+                write!(f, "The command '{}' wraps '{}', which is not a command.", name, path)
+            }
+            BuildError::DuplicateParameter(ref command, ref name) => {
+                write!(f, "The command '{}' has more than one parameter '{}'.", command, name)
+            }
+            BuildError::SlotConflict(ref command, ref slot) => {
+                write!(f, "The command '{}' has a choice and a parameter '{}'.", command, slot)
+            }
+            BuildError::GreedyNotSimple(ref command, ref name) => {
+                write!(f, "The parameter '{}' of '{}' is greedy but not simple.", name, command)
+            }
+            BuildError::UnknownParameter(ref name, ref target) => {
+                write!(
+                    f,
+                    "The parameter '{}' refers to '{}', which is not a parameter.",
+                    name,
+                    target
+                )
+            }
+        }
+    }
+}
+
 /// Description of a command to be added to the [`CommandTree`].
 ///
 /// The lifetime parameter `'a` refers to the lifetime
 /// of the strings used for command names and help text.
 ///
+/// With the `serde` feature, commands can be serialized and
+/// deserialized, such as to load them from a file. The name of
+/// each command and parameter must be given, while any other
+/// fields which aren't given take their default values. Handlers
+/// are not included. Strings are borrowed from the input, so
+/// they can't contain escape sequences.
+///
 /// [`CommandTree`]: struct.CommandTree.html
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Command<'a> {
+    #[cfg_attr(feature = "serde", serde(default))]
     hidden: bool,
-    priority: i32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    priority: Option<i32>,
     name: &'a str,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    aliases: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
     help_text: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
     parameters: Vec<Parameter<'a>>,
+    #[cfg_attr(feature = "serde", serde(default))]
     wrapped_root: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    handler: Option<CommandHandler>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    see_also: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    exclusive_groups: Vec<Vec<&'a str>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    auto_short_alias: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    deprecated: bool,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    help_header: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    help_footer: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    help_symbol: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    required_token: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    subcommands: Vec<Command<'a>>,
+}
+
+impl<'a> fmt::Debug for Command<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("help_text", &self.help_text)
+            .field("hidden", &self.hidden)
+            .field("deprecated", &self.deprecated)
+            .field("priority", &self.priority)
+            .field("has_handler", &self.handler.is_some())
+            .field("parameters", &self.parameters)
+            .field("subcommands", &self.subcommands)
+            .finish()
+    }
 }
 
 impl<'a> Command<'a> {
@@ -186,14 +1021,32 @@ impl<'a> Command<'a> {
     pub fn new(name: &'a str) -> Self {
         Command {
             hidden: false,
-            priority: PRIORITY_DEFAULT,
+            priority: None,
             name: name,
+            aliases: vec![],
             help_text: None,
             parameters: vec![],
             wrapped_root: None,
+            handler: None,
+            see_also: vec![],
+            exclusive_groups: vec![],
+            auto_short_alias: false,
+            deprecated: false,
+            help_header: None,
+            help_footer: None,
+            help_symbol: None,
+            required_token: None,
+            subcommands: vec![],
         }
     }
 
+    /// Add an alias which matches this command when it is given
+    /// in full, as `display` might be for `show`.
+    pub fn alias(mut self, alias: &'a str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+
     /// Mark the command as hidden. Hidden commands will match
     /// within the parser, but are not listed during completion.
     pub fn hidden(mut self, hidden: bool) -> Self {
@@ -201,12 +1054,28 @@ impl<'a> Command<'a> {
         self
     }
 
+    /// Mark the command as deprecated. Deprecated commands will
+    /// match within the parser, but their use is reported to the
+    /// deprecation handler of the [`Parser`].
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn deprecated(mut self, deprecated: bool) -> Self {
+        self.deprecated = deprecated;
+        self
+    }
+
     /// Give the command a priority. This is used when sorting
     /// out conflicts during matching and completion.
     ///
+    /// The `priority` of a `Command` defaults to the default
+    /// priority of the [`CommandTree`], which is `PRIORITY_DEFAULT`
+    /// unless otherwise specified.
+    ///
     /// This is not commonly needed.
+    ///
+    /// [`CommandTree`]: struct.CommandTree.html
     pub fn priority(mut self, priority: i32) -> Self {
-        self.priority = priority;
+        self.priority = Some(priority);
         self
     }
 
@@ -216,6 +1085,37 @@ impl<'a> Command<'a> {
         self
     }
 
+    /// Require that a confirmation `token`, like `--yes`, is given
+    /// for the command to pass `verify`. This guards commands which
+    /// are dangerous to run by accident.
+    ///
+    /// The token is added to the command as a flag.
+    pub fn require_token(mut self, token: &'a str) -> Self {
+        self.required_token = Some(token);
+        self
+    }
+
+    /// Supply the symbol used for the command in help and
+    /// completion, in place of its name.
+    pub fn help_symbol_override(mut self, symbol: &'a str) -> Self {
+        self.help_symbol = Some(symbol);
+        self
+    }
+
+    /// Supply text to be shown in help before the parameters
+    /// of the command.
+    pub fn help_header(mut self, header: &'a str) -> Self {
+        self.help_header = Some(header);
+        self
+    }
+
+    /// Supply text to be shown in help after the parameters
+    /// of the command.
+    pub fn help_footer(mut self, footer: &'a str) -> Self {
+        self.help_footer = Some(footer);
+        self
+    }
+
     /// Add a [`Parameter`] to the command.
     ///
     /// [`Parameter`]: struct.Parameter.html
@@ -224,14 +1124,158 @@ impl<'a> Command<'a> {
         self
     }
 
+    /// Add a choice between the `members` to the command.
+    ///
+    /// Only one of the members may be given, so `verify` fails with
+    /// `VerifyError::ConflictingParameters` when more than one is.
+    /// Whichever is given fills the shared `slot`, so that the name
+    /// of the member which was chosen is available from the values
+    /// of the parser under `slot`, as well as its own value being
+    /// available under its own name.
+    ///
+    /// As they share the values of the parser, `try_finalize` fails
+    /// with `BuildError::SlotConflict` when the `slot` has the name
+    /// of a parameter of the command.
+    pub fn choice(mut self, slot: &'a str, members: Vec<Parameter<'a>>) -> Self {
+        for mut member in members {
+            member.slot = Some(slot);
+            self.parameters.push(member);
+        }
+        self
+    }
+
+    /// Give the command a single-character alias derived from the
+    /// first letter of its name.
+    ///
+    /// If that alias is already the name or alias of another
+    /// command, the command is left without it and a
+    /// [`BuildWarning`] is recorded by `finalize`.
+    ///
+    /// [`BuildWarning`]: enum.BuildWarning.html
+    pub fn auto_short_alias(mut self, auto_short_alias: bool) -> Self {
+        self.auto_short_alias = auto_short_alias;
+        self
+    }
+
+    /// Declare that at most one of the parameters with the given
+    /// `names` may be given. `Parser::verify` fails with
+    /// `VerifyError::ConflictingParameters` otherwise. Default
+    /// values and values from the context don't count as given.
+    ///
+    /// `finalize` records a [`BuildWarning`] if more than one of
+    /// them is also `required`, as the command could then never
+    /// be executed.
+    ///
+    /// [`BuildWarning`]: enum.BuildWarning.html
+    pub fn exclusive_group(mut self, names: &[&'a str]) -> Self {
+        self.exclusive_groups.push(names.to_vec());
+        self
+    }
+
+    /// Supply the names of related commands so that help
+    /// can cross-reference them.
+    pub fn see_also(mut self, names: &[&'a str]) -> Self {
+        self.see_also.extend_from_slice(names);
+        self
+    }
+
+    /// Supply the handler which is invoked when the command
+    /// is executed by the [`Parser`].
+    ///
+    /// The handler may be a closure, so it can capture whatever
+    /// state it needs to act upon.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Parser) -> ExecStatus + 'static,
+    {
+        self.handler = Some(Rc::new(move |parser| Ok(handler(parser))));
+        self
+    }
+
+    /// Supply a handler which may fail, giving a message saying
+    /// why. The failure is given by `try_execute` on the [`Parser`]
+    /// as an `ExecError` naming the command.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn try_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Parser) -> Result<ExecStatus, String> + 'static,
+    {
+        self.handler = Some(Rc::new(handler));
+        self
+    }
+
+    /// Add a `subcommand` which may be given after this command,
+    /// as with `interface` in `show interface`.
+    ///
+    /// Subcommands may themselves have subcommands. When a
+    /// subcommand is given, it is the one which is executed,
+    /// rather than this command, but the parameters of both
+    /// are verified.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show")
+    ///                  .subcommand(Command::new("interface")
+    ///                                  .subcommand(Command::new("brief"))));
+    /// ```
+    pub fn subcommand(mut self, subcommand: Command<'a>) -> Self {
+        self.subcommands.push(subcommand);
+        self
+    }
+
     /// The `wrapped_root` signifies the path to the command that should
     /// be wrapped by this command. This is used for the `help` command.
     ///
+    /// The path is given as the names of commands separated by
+    /// whitespace, like `"show interface"`. The parameters and
+    /// subcommands of the wrapped command may then follow this
+    /// command. An empty path wraps all of the top level commands
+    /// which don't themselves wrap another command.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show").subcommand(Command::new("interface")));
+    /// tree.command(Command::new("int").wraps("show interface".to_string()));
+    /// tree.command(Command::new("help").wraps(String::new()));
+    /// assert!(tree.try_finalize().is_ok());
+    /// ```
+    ///
     /// [`CommandNode`]: struct.CommandNode.html
     pub fn wraps(mut self, wrapped_root: String) -> Self {
         self.wrapped_root = Some(wrapped_root);
         self
     }
+
+    fn diff(&self, other: &Command) -> CommandDiff {
+        let mut diff = CommandDiff {
+            name: self.name.to_string(),
+            changed: self.help_text != other.help_text || self.hidden != other.hidden ||
+                self.deprecated != other.deprecated,
+            ..Default::default()
+        };
+        for parameter in &self.parameters {
+            match other.parameters.iter().find(|p| p.name == parameter.name) {
+                Some(other) if !parameter.same_as(other) => {
+                    diff.changed_parameters.push(parameter.name.to_string());
+                }
+                Some(_) => {}
+                None => diff.removed_parameters.push(parameter.name.to_string()),
+            }
+        }
+        for parameter in &other.parameters {
+            if !self.parameters.iter().any(|p| p.name == parameter.name) {
+                diff.added_parameters.push(parameter.name.to_string());
+            }
+        }
+        diff
+    }
 }
 
 /// Description of a parameter to be added to the [`Command`].
@@ -241,15 +1285,83 @@ impl<'a> Command<'a> {
 /// help text.
 ///
 /// [`Command`]: struct.Command.html
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Parameter<'a> {
+    #[cfg_attr(feature = "serde", serde(default))]
     hidden: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
     priority: Option<i32>,
     name: &'a str,
+    #[cfg_attr(feature = "serde", serde(default))]
     repeatable: bool,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
     aliases: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
     help_text: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(default = "Parameter::default_kind"))]
     kind: ParameterKind,
+    #[cfg_attr(feature = "serde", serde(default))]
     required: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    choices: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default = "Parameter::default_path_validation"))]
+    path_validation: PathValidation,
+    #[cfg_attr(feature = "serde", serde(default = "Parameter::default_value_type"))]
+    value_type: ValueType,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    context_key: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    auto_short_alias: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    deprecated: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    greedy: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    negatable: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    short: Option<char>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    experimental: bool,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    help_symbol: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    slot: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    default_value: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    min_occurs: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_occurs: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    requires: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    conflicts_with: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    completer: Option<ValueCompleter>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    list_separator: Option<char>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    value_optional: bool,
+}
+
+impl<'a> fmt::Debug for Parameter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Parameter")
+            .field("name", &self.name)
+            .field("kind", &self.kind)
+            .field("aliases", &self.aliases)
+            .field("short", &self.short)
+            .field("help_text", &self.help_text)
+            .field("hidden", &self.hidden)
+            .field("deprecated", &self.deprecated)
+            .field("priority", &self.priority)
+            .field("required", &self.required)
+            .field("repeatable", &self.repeatable)
+            .field("choices", &self.choices)
+            .field("default_value", &self.default_value)
+            .field("has_completer", &self.completer.is_some())
+            .finish()
+    }
 }
 
 impl<'a> Parameter<'a> {
@@ -264,9 +1376,49 @@ impl<'a> Parameter<'a> {
             help_text: None,
             kind: ParameterKind::Simple,
             required: false,
+            choices: vec![],
+            path_validation: PathValidation::None,
+            value_type: ValueType::String,
+            context_key: None,
+            auto_short_alias: false,
+            deprecated: false,
+            greedy: false,
+            negatable: false,
+            short: None,
+            experimental: false,
+            help_symbol: None,
+            slot: None,
+            default_value: None,
+            min_occurs: 0,
+            max_occurs: None,
+            requires: vec![],
+            conflicts_with: vec![],
+            completer: None,
+            list_separator: None,
+            value_optional: false,
         }
     }
 
+    /// The kind of a deserialized parameter which doesn't give one.
+    #[cfg(feature = "serde")]
+    fn default_kind() -> ParameterKind {
+        ParameterKind::Simple
+    }
+
+    /// The path validation of a deserialized parameter which
+    /// doesn't give one.
+    #[cfg(feature = "serde")]
+    fn default_path_validation() -> PathValidation {
+        PathValidation::None
+    }
+
+    /// The value type of a deserialized parameter which doesn't
+    /// give one.
+    #[cfg(feature = "serde")]
+    fn default_value_type() -> ValueType {
+        ValueType::String
+    }
+
     /// Mark the parameter as hidden. Hidden parameters will match
     /// within the parser, but are not listed during completion.
     pub fn hidden(mut self, hidden: bool) -> Self {
@@ -274,6 +1426,16 @@ impl<'a> Parameter<'a> {
         self
     }
 
+    /// Mark the parameter as deprecated. Deprecated parameters will
+    /// match within the parser, but their use is reported to the
+    /// deprecation handler of the [`Parser`].
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn deprecated(mut self, deprecated: bool) -> Self {
+        self.deprecated = deprecated;
+        self
+    }
+
     /// Give the parameter a priority. This is used when sorting
     /// out conflicts during matching and completion.
     ///
@@ -290,32 +1452,313 @@ impl<'a> Parameter<'a> {
     /// Establish whether or not this parameter is repeatable.
     /// Repeated parameters produce a vector of values and can
     /// be given multiple times within a single command invocation.
+    ///
+    /// A parameter which isn't repeatable doesn't match again once
+    /// it has been given, so giving it twice fails to parse.
     pub fn repeatable(mut self, repeatable: bool) -> Self {
         self.repeatable = repeatable;
         self
     }
 
+    /// Set the fewest times that a repeatable parameter must be
+    /// given when it is given at all.
+    ///
+    /// A parameter which is left out entirely isn't checked, so
+    /// `required` should also be used when the parameter must be
+    /// given at least `min_occurs` times.
+    ///
+    /// This is checked by `verify` on the [`Parser`].
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn min_occurs(mut self, min_occurs: usize) -> Self {
+        self.min_occurs = min_occurs;
+        self
+    }
+
+    /// Set the most times that a repeatable parameter may be given.
+    ///
+    /// This is checked by `verify` on the [`Parser`].
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn max_occurs(mut self, max_occurs: usize) -> Self {
+        self.max_occurs = Some(max_occurs);
+        self
+    }
+
+    /// Require that the parameter with the given `name` is also
+    /// given whenever this parameter is given, as `--port` may
+    /// require `--host`.
+    ///
+    /// This is checked by `verify` on the [`Parser`]. The `name`
+    /// must be that of another parameter of the same command, or
+    /// `try_finalize` fails with `BuildError::UnknownParameter`.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn requires(mut self, name: &'a str) -> Self {
+        self.requires.push(name);
+        self
+    }
+
+    /// Forbid the parameter with the given `name` from being given
+    /// along with this parameter, as `--quiet` may conflict with
+    /// `--verbose`.
+    ///
+    /// This is checked by `verify` on the [`Parser`]. The `name`
+    /// must be that of another parameter of the same command, or
+    /// `try_finalize` fails with `BuildError::UnknownParameter`.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn conflicts_with(mut self, name: &'a str) -> Self {
+        self.conflicts_with.push(name);
+        self
+    }
+
     /// Add an alias that this parameter can use.
     ///
-    /// Aliases are currently only valid for parameters of `kind`
-    /// `ParameterKind::Named`.
+    /// Aliases are only used by parameters of `kind`
+    /// `ParameterKind::Flag` or `ParameterKind::Named`. Values
+    /// are recorded under the name of the parameter, even when
+    /// it was given by an alias. Aliases must be given in full and
+    /// only the name of the parameter is offered during completion.
+    ///
+    /// `CommandTree::try_finalize` fails if an alias is also the
+    /// name or alias of another parameter of the same command.
     pub fn alias(mut self, alias: &'a str) -> Self {
         self.aliases.push(alias);
         self
     }
 
+    /// Give the parameter a single-character short form, so that
+    /// `--verbose` may also be given as `-v`.
+    ///
+    /// When short clusters are enabled on the [`Parser`], several
+    /// short flags may be given together, as `-abc` for `-a -b -c`.
+    ///
+    /// Short forms are only used by parameters of `kind`
+    /// `ParameterKind::Flag` or `ParameterKind::Named`.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn short(mut self, short: char) -> Self {
+        self.short = Some(short);
+        self
+    }
+
+    /// Give the parameter a single-character alias derived from
+    /// the first letter of its name, so that `--verbose` may also
+    /// be given as `-v`.
+    ///
+    /// If that alias is already the name or alias of another
+    /// parameter of the command, the parameter is left without it
+    /// and a [`BuildWarning`] is recorded by `finalize`.
+    ///
+    /// Short aliases are only used by parameters of `kind`
+    /// `ParameterKind::Flag` or `ParameterKind::Named`.
+    ///
+    /// [`BuildWarning`]: enum.BuildWarning.html
+    pub fn auto_short_alias(mut self, auto_short_alias: bool) -> Self {
+        self.auto_short_alias = auto_short_alias;
+        self
+    }
+
     /// Supply the help text for the parameter.
     pub fn help(mut self, help_text: &'a str) -> Self {
         self.help_text = Some(help_text);
         self
     }
 
+    /// Supply the symbol used for the parameter in help and
+    /// completion, in place of the one generated from its kind
+    /// and whether or not it is required. The symbol is used
+    /// verbatim.
+    pub fn help_symbol_override(mut self, symbol: &'a str) -> Self {
+        self.help_symbol = Some(symbol);
+        self
+    }
+
     /// Establish whether or not this parameter is required.
     pub fn required(mut self, required: bool) -> Self {
         self.required = required;
         self
     }
 
+    /// Supply the values which this parameter will accept.
+    ///
+    /// Only a prefix of a choice is needed for the value to match.
+    /// A prefix of just one choice is recorded as that choice, while
+    /// `verify` on the [`Parser`] fails with
+    /// `VerifyError::InvalidChoice` for a prefix of several. When the
+    /// parser is case insensitive, so are the choices. The choices
+    /// are offered during completion.
+    ///
+    /// Choices are only valid for parameters of `kind`
+    /// `ParameterKind::Named` or `ParameterKind::Simple`.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn choices(mut self, choices: &[&str]) -> Self {
+        self.choices.extend(choices.iter().map(|c| c.to_string()));
+        self
+    }
+
+    /// Supply the values which this parameter will accept, as
+    /// with `choices`.
+    ///
+    /// The choices can come from any iterator, so they may be
+    /// populated at runtime, such as from a file read by the caller.
+    ///
+    /// Choices are only valid for parameters of `kind`
+    /// `ParameterKind::Named` or `ParameterKind::Simple`.
+    pub fn choices_from_iter<I>(mut self, choices: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.choices.extend(choices);
+        self
+    }
+
+    /// Allow this parameter to be given without a value, as with
+    /// `--log` as well as `--log debug`.
+    ///
+    /// When the name of the parameter is followed by nothing or by
+    /// a token starting with `-`, such as another flag, no value is
+    /// taken. The parameter is then recorded with its default value
+    /// if it has one, and otherwise with an empty value. A value
+    /// starting with `-` may be given as `--log=-1`.
+    ///
+    /// This is only used by parameters of `kind`
+    /// `ParameterKind::Named`.
+    pub fn value_optional(mut self, value_optional: bool) -> Self {
+        self.value_optional = value_optional;
+        self
+    }
+
+    /// Split the value of this parameter on `separator`, so that a
+    /// single token like `red,green,blue` gives several values. The
+    /// values are available from `Parser::parameters` as a
+    /// `ParameterValue::Multiple`, even when there is only one.
+    ///
+    /// Empty elements, such as from `a,,b` or a trailing separator,
+    /// are dropped. The `choices` and `value_type` of the parameter
+    /// apply to each element.
+    ///
+    /// List separators are only used by parameters of `kind`
+    /// `ParameterKind::Named` or `ParameterKind::Simple`.
+    pub fn list_separator(mut self, separator: char) -> Self {
+        self.list_separator = Some(separator);
+        self
+    }
+
+    /// Supply a function which provides values to offer when
+    /// completing the value of this parameter, such as the names
+    /// of resources which are only known at runtime.
+    ///
+    /// The `completer` is called with the text of the value typed
+    /// so far, which is empty when nothing has been typed. Its
+    /// results are offered along with any `choices`, filtered by
+    /// that text. Unlike choices, they don't restrict matching.
+    ///
+    /// Completers are only used by parameters of `kind`
+    /// `ParameterKind::Named` or `ParameterKind::Simple`.
+    pub fn complete_with<F>(mut self, completer: F) -> Self
+    where
+        F: Fn(&str) -> Vec<String> + 'static,
+    {
+        self.completer = Some(Rc::new(completer));
+        self
+    }
+
+    /// Mark the value of this parameter as a path, which `verify`
+    /// validates according to `validation`.
+    ///
+    /// Validation requires that a [`FileSystem`] has been given to
+    /// the [`Parser`].
+    ///
+    /// [`FileSystem`]: trait.FileSystem.html
+    /// [`Parser`]: struct.Parser.html
+    pub fn path(mut self, validation: PathValidation) -> Self {
+        self.path_validation = validation;
+        self
+    }
+
+    /// Give the type of the value of this parameter. `verify`
+    /// fails if the value can't be parsed as this type, and the
+    /// parsed value is available from the [`Parser`].
+    ///
+    /// This defaults to `ValueType::String`.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn value_type(mut self, value_type: ValueType) -> Self {
+        self.value_type = value_type;
+        self
+    }
+
+    /// Mark the parameter as experimental. Experimental parameters
+    /// neither match nor complete unless experimental parameters
+    /// have been enabled on the [`Parser`].
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn experimental(mut self, experimental: bool) -> Self {
+        self.experimental = experimental;
+        self
+    }
+
+    /// Make this parameter take the rest of the command line once
+    /// the command has been matched, without interpreting any of it
+    /// as flags or other parameters. This is useful for commands
+    /// like `echo`.
+    ///
+    /// If the rest of the command line contains `--`, the tokens
+    /// before it are parsed as usual and only those after it are
+    /// taken as the value.
+    ///
+    /// Greedy parameters must be of `kind` `ParameterKind::Simple`.
+    /// `CommandTree::try_finalize` fails with
+    /// `BuildError::GreedyNotSimple` for any other kind.
+    pub fn greedy(mut self, greedy: bool) -> Self {
+        self.greedy = greedy;
+        self
+    }
+
+    /// Allow a flag to be negated, so that `--no-color` may be given
+    /// to turn off `--color`. The flag then has the value `true` when
+    /// it is given and `false` when its negation is given.
+    ///
+    /// Giving both the flag and its negation is reported by `verify`
+    /// on the [`Parser`].
+    ///
+    /// Only parameters of `kind` `ParameterKind::Flag` are negatable.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn negatable(mut self, negatable: bool) -> Self {
+        self.negatable = negatable;
+        self
+    }
+
+    /// Take the value of this parameter from the context of the
+    /// [`Parser`] under `key` when it isn't given on the command
+    /// line. This allows the output of one command to be used by
+    /// the next.
+    ///
+    /// A value given on the command line takes precedence over
+    /// the context.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn from_context(mut self, key: &'a str) -> Self {
+        self.context_key = Some(key);
+        self
+    }
+
+    /// Supply the value of this parameter for when it isn't given
+    /// on the command line, or by the context of the [`Parser`].
+    ///
+    /// A parameter which is given an empty value does not take the
+    /// default value.
+    ///
+    /// [`Parser`]: struct.Parser.html
+    pub fn default_value(mut self, value: &'a str) -> Self {
+        self.default_value = Some(value);
+        self
+    }
+
     /// Set which type of [`ParameterNode`] is supposed to be created
     /// to represent this parameter.
     ///
@@ -324,4 +1767,23 @@ impl<'a> Parameter<'a> {
         self.kind = kind;
         self
     }
+
+    /// The aliases of this parameter, including its short form.
+    fn alias_names(&self) -> Vec<String> {
+        let short = self.short.map(|c| format!("-{}", c));
+        self.aliases.iter().map(|a| a.to_string()).chain(short).collect()
+    }
+
+    fn same_as(&self, other: &Parameter) -> bool {
+        self.kind == other.kind && self.required == other.required &&
+            self.repeatable == other.repeatable && self.hidden == other.hidden &&
+            self.deprecated == other.deprecated && self.aliases == other.aliases &&
+            self.help_text == other.help_text && self.choices == other.choices &&
+            self.slot == other.slot && self.default_value == other.default_value &&
+            self.min_occurs == other.min_occurs && self.max_occurs == other.max_occurs &&
+            self.negatable == other.negatable && self.short == other.short &&
+            self.requires == other.requires && self.conflicts_with == other.conflicts_with &&
+            self.list_separator == other.list_separator &&
+            self.value_optional == other.value_optional
+    }
 }
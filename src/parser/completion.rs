@@ -4,8 +4,61 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::{Node, Parser};
 use tokenizer::Token;
-use util::longest_common_prefix;
+use util::{is_prefix, longest_common_prefix};
+
+/// Something which can complete a line of input, such as for a
+/// line editor.
+///
+/// This lets the integration with an editor be written once for
+/// anything which can complete. It is implemented by a
+/// [`TreeCompleter`] for a tree built by a [`CommandTree`].
+///
+/// [`CommandTree`]: struct.CommandTree.html
+/// [`TreeCompleter`]: struct.TreeCompleter.html
+pub trait Completer {
+    /// Get the possible completions for a `line` of input, given
+    /// the byte offset of the `cursor` within the line.
+    fn complete<'text>(&self, line: &'text str, cursor: usize) -> Vec<Completion<'text>>;
+}
+
+/// A [`Completer`] for the commands in a tree, which completes
+/// each line with a fresh [`Parser`].
+///
+/// ```
+/// use commands::parser::{Command, CommandTree, Completer, TreeCompleter};
+///
+/// let mut tree = CommandTree::new();
+/// tree.command(Command::new("show"));
+/// let completer = TreeCompleter::new(tree.finalize());
+/// let completions = completer.complete("sh", 2);
+/// assert_eq!(completions[0].options[0].option_string, "show");
+/// ```
+///
+/// [`Completer`]: trait.Completer.html
+/// [`Parser`]: struct.Parser.html
+pub struct TreeCompleter {
+    root: Rc<Node>,
+}
+
+impl TreeCompleter {
+    /// Construct a `TreeCompleter` for the tree with the given `root`.
+    pub fn new(root: Rc<Node>) -> Self {
+        TreeCompleter { root: root }
+    }
+}
+
+impl Completer for TreeCompleter {
+    /// Complete the `line` with `Parser::complete_line`, giving no
+    /// completions when it fails.
+    fn complete<'text>(&self, line: &'text str, cursor: usize) -> Vec<Completion<'text>> {
+        Parser::new(Rc::clone(&self.root)).complete_line(line, cursor).unwrap_or_default()
+    }
+}
 
 /// Represents a single option returned by `complete`.
 ///
@@ -54,6 +107,13 @@ pub struct Completion<'text> {
     pub exhaustive: bool,
     /// The actual completion options.
     pub options: Vec<CompletionOption>,
+    /// The byte range within the line of the text which the
+    /// options replace. This is the span of the token, if
+    /// provided, and is otherwise empty.
+    pub replace_span: Range<usize>,
+    /// Is the node being completed hidden? Hidden nodes are only
+    /// completed when the parser has been asked to show them.
+    pub hidden: bool,
 }
 
 impl<'text> Completion<'text> {
@@ -84,8 +144,8 @@ impl<'text> Completion<'text> {
         if let Some(t) = token {
             // Filter options using token.
             let token_text = t.text.to_string();
-            complete_options.retain(|o| o.starts_with(t.text));
-            other_options.retain(|o| o.starts_with(t.text));
+            complete_options.retain(|o| is_prefix(o, t.text));
+            other_options.retain(|o| is_prefix(o, t.text));
             // If not exhaustive, then add the current token as
             // an incomplete option.
             if !exhaustive && !complete_options.contains(&token_text) &&
@@ -121,6 +181,8 @@ impl<'text> Completion<'text> {
             token: token,
             exhaustive: exhaustive,
             options: options,
+            replace_span: token.map_or(0..0, |t| t.span()),
+            hidden: false,
         }
     }
 }
@@ -6,7 +6,8 @@
 
 /// Indicate the type of parameter, so that the correct class and node
 /// structures are created.
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ParameterKind {
     /// This parameter is a flag parameter.
     Flag,
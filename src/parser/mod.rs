@@ -114,20 +114,34 @@ mod builder;
 mod completion;
 mod constants;
 mod nodes;
+#[cfg(feature = "serde")]
+mod spec;
+mod validation;
 
 // Re-export public API
-pub use self::builder::{Command, CommandTree, Parameter};
+pub use self::builder::{BuildError, BuildWarning, Command, CommandDiff, CommandTree};
+pub use self::builder::{Parameter, TreeDiff};
 pub use self::constants::ParameterKind;
 pub use self::constants::{PRIORITY_DEFAULT, PRIORITY_MINIMUM, PRIORITY_PARAMETER};
-pub use self::completion::{Completion, CompletionOption};
-pub use self::nodes::{Node, NodeOps, TreeNode};
-pub use self::nodes::{CommandNode, ParameterNameNode, ParameterNode, RootNode};
+pub use self::completion::{Completer, Completion, CompletionOption, TreeCompleter};
+pub use self::nodes::{Node, NodeOps, TreeNode, UnknownCommandMessage};
+pub use self::nodes::{CommandNode, LazyDefinition, ParameterNameNode, ParameterNode, RootNode};
+#[cfg(feature = "serde")]
+pub use self::spec::SpecError;
+pub use self::validation::{FileSystem, PathValidation, StdFileSystem, TypedValue, ValueType};
 
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::ops::Range;
+use std::path::Path;
 use std::rc::Rc;
-use tokenizer::{Token, TokenType};
+use std::time::{Duration, Instant};
+use tokenizer::{quote, tokenize, SourceLocation, SourceOffset, Token, TokenType};
+use tokenizer::TokenizerError;
+use util::{fuzzy_score, is_prefix, is_prefix_ignoring_case};
 
 /// Command parser
 ///
@@ -155,6 +169,7 @@ use tokenizer::{Token, TokenType};
 /// [`CommandTree`]: struct.CommandTree.html
 /// ['RootNode`]: struct.RootNode.html
 pub struct Parser<'text> {
+    root: Rc<Node>,
     current_node: Rc<Node>,
     /// The nodes which have been accepted during `parse` or `advance`.
     pub nodes: Vec<Rc<Node>>,
@@ -162,26 +177,339 @@ pub struct Parser<'text> {
     pub tokens: Vec<Token<'text>>,
     commands: Vec<Rc<Node>>,
     parameters: HashMap<String, String>,
+    repeated_values: HashMap<String, Vec<String>>,
+    last_good_position: usize,
+    near_misses: Vec<Rc<Node>>,
+    options_terminated: bool,
+    file_system: Option<Box<dyn FileSystem>>,
+    tracing: bool,
+    trace: Vec<TraceEvent>,
+    exact_wins: bool,
+    case_insensitive: bool,
+    prefix_matching: bool,
+    complete_aliases: bool,
+    show_hidden: bool,
+    short_clusters: bool,
+    equals_separator: bool,
+    named_value_policy: NamedValuePolicy,
+    timing: bool,
+    timings: Cell<Timings>,
+    context: RefCell<HashMap<String, String>>,
+    deprecation_handler: Option<DeprecationHandler>,
+    usage_stats: HashMap<String, u64>,
+    recording_usage: bool,
+    usage_counts: RefCell<HashMap<String, u64>>,
+    experimental: bool,
+    recovering: bool,
+    recovered_errors: Vec<ParseError<'text>>,
+    unmatched: Vec<Token<'text>>,
 }
 
 impl<'text> Parser<'text> {
     /// Construct a parser with a root node.
+    ///
+    /// This is cheap, as the tree is shared rather than copied, so
+    /// a new parser may be created for each line of input.
+    ///
+    /// Nothing is cached between parses. A token which is the full
+    /// name or an alias of a successor of the current node is looked
+    /// up by name from an index built with the tree. Other tokens,
+    /// like prefixes and values, are matched by checking every
+    /// successor, so they take time proportional to the number of
+    /// successors. When a token doesn't match, the error holds the
+    /// successors which were acceptable.
     pub fn new(initial_node: Rc<Node>) -> Parser<'text> {
         Parser {
+            root: Rc::clone(&initial_node),
             current_node: initial_node,
             nodes: vec![],
             tokens: vec![],
             commands: vec![],
             parameters: HashMap::new(),
+            repeated_values: HashMap::new(),
+            last_good_position: 0,
+            near_misses: vec![],
+            options_terminated: false,
+            file_system: None,
+            tracing: false,
+            trace: vec![],
+            exact_wins: true,
+            case_insensitive: false,
+            prefix_matching: true,
+            complete_aliases: false,
+            show_hidden: false,
+            short_clusters: false,
+            equals_separator: false,
+            named_value_policy: NamedValuePolicy::Value,
+            timing: false,
+            timings: Cell::new(Timings::default()),
+            context: RefCell::new(HashMap::new()),
+            deprecation_handler: None,
+            usage_stats: HashMap::new(),
+            recording_usage: false,
+            usage_counts: RefCell::new(HashMap::new()),
+            experimental: false,
+            recovering: false,
+            recovered_errors: vec![],
+            unmatched: vec![],
+        }
+    }
+
+    /// Supply the [`FileSystem`] used by `verify` to validate the
+    /// values of path parameters.
+    ///
+    /// Without a filesystem, path parameters are not validated.
+    ///
+    /// [`FileSystem`]: trait.FileSystem.html
+    pub fn set_file_system(&mut self, file_system: Box<dyn FileSystem>) {
+        self.file_system = Some(file_system);
+    }
+
+    /// Set whether or not `parse` recovers from tokens which don't
+    /// match any node.
+    ///
+    /// When recovering, a token which doesn't match is skipped and
+    /// parsing continues with the next token, so that the errors in
+    /// messy input can be found in a single pass. The errors are
+    /// available from `recovered_errors`. Ambiguous tokens are still
+    /// treated as errors. This defaults to `false`.
+    pub fn set_recovery(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    /// The errors for the tokens which were skipped during the
+    /// last `parse` when recovery is enabled.
+    pub fn recovered_errors(&self) -> &[ParseError<'text>] {
+        &self.recovered_errors
+    }
+
+    /// Set whether or not a token which exactly matches the name
+    /// of a node wins over other nodes which it is a prefix of.
+    ///
+    /// With commands `show` and `showtech`, the token `show` is
+    /// treated as the command `show` rather than being ambiguous.
+    /// This defaults to `true`.
+    pub fn set_exact_wins(&mut self, exact_wins: bool) {
+        self.exact_wins = exact_wins;
+    }
+
+    /// Set whether or not a name may be abbreviated to any prefix
+    /// of it, such as `sh` for `show`, so long as the prefix isn't
+    /// ambiguous.
+    ///
+    /// When more than one name has the prefix, those with the
+    /// highest priority are preferred, and if that still leaves
+    /// more than one, the token is an `AmbiguousMatch`. When this
+    /// is `false`, names must be given in full, or by an alias.
+    /// This defaults to `true`.
+    pub fn set_prefix_matching(&mut self, prefix_matching: bool) {
+        self.prefix_matching = prefix_matching;
+    }
+
+    /// Set whether or not the aliases of commands are given as
+    /// completion options alongside their names. This defaults
+    /// to `false`.
+    pub fn set_complete_aliases(&mut self, complete_aliases: bool) {
+        self.complete_aliases = complete_aliases;
+    }
+
+    /// Set whether or not hidden commands and parameters are
+    /// completed and included in `expectations`, such as for
+    /// debugging tools. Their completions are marked as `hidden`.
+    /// This defaults to `false`.
+    ///
+    /// Help including hidden nodes is given by
+    /// `RootNode::help_listing_with_hidden`,
+    /// `RootNode::command_help_with_hidden` and
+    /// `CommandNode::help_with_hidden`.
+    pub fn set_show_hidden(&mut self, show_hidden: bool) {
+        self.show_hidden = show_hidden;
+    }
+
+    /// Set whether or not the names of commands and parameters
+    /// are matched without regard to ASCII case, so that `SHOW`
+    /// matches the command `show`.
+    ///
+    /// Completions are also found without regard to case, but
+    /// give names as they were defined. This defaults to `false`.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    /// Set whether or not a cluster of short flags, like `-vx`,
+    /// is expanded into the flags `-v` and `-x`.
+    ///
+    /// A cluster is only expanded when it doesn't match the name
+    /// of a node as given and it starts with a short flag, so that
+    /// values like `-12` are left alone. Each flag in the cluster is
+    /// matched as though it had been given separately, so a flag
+    /// which is not `repeatable` can not be given twice within a
+    /// cluster. When a flag in the cluster doesn't match, the error
+    /// gives a token for just that flag's character. This defaults
+    /// to `false`.
+    pub fn set_short_clusters(&mut self, short_clusters: bool) {
+        self.short_clusters = short_clusters;
+    }
+
+    /// Set whether or not a `=` token following the name of a
+    /// named parameter is consumed as a separator, so that
+    /// `name = value` binds `value`. Such tokens are produced when
+    /// tokenizing with the `equals_separator` option, so the two
+    /// should be enabled together. Otherwise, a `=` token is the
+    /// value of the parameter. This defaults to `false`.
+    pub fn set_equals_separator(&mut self, equals_separator: bool) {
+        self.equals_separator = equals_separator;
+    }
+
+    /// Set how a token following the name of a named parameter is
+    /// treated when it could also be the name of another node.
+    ///
+    /// This defaults to `NamedValuePolicy::Value`, so that the
+    /// token is taken as the value of the parameter.
+    pub fn set_named_value_policy(&mut self, policy: NamedValuePolicy) {
+        self.named_value_policy = policy;
+    }
+
+    /// Supply a handler which is called with the name of each
+    /// deprecated command or parameter as it is matched.
+    ///
+    /// This allows the use of deprecated commands to be logged
+    /// or counted.
+    pub fn set_deprecation_handler(&mut self, handler: DeprecationHandler) {
+        self.deprecation_handler = Some(handler);
+    }
+
+    /// Enable or disable tracing of the parser.
+    ///
+    /// While tracing, a [`TraceEvent`] is recorded for each token
+    /// which is matched, giving both the token as it was typed and
+    /// the canonical name of the node which it matched. This helps
+    /// to debug why input matched when it was abbreviated or given
+    /// using an alias.
+    ///
+    /// [`TraceEvent`]: struct.TraceEvent.html
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.tracing = enabled;
+    }
+
+    /// The events recorded while tracing.
+    pub fn trace(&self) -> &[TraceEvent] {
+        &self.trace
+    }
+
+    /// Enable or disable timing of the parser.
+    ///
+    /// While timing, the time taken by `parse`, `verify` and
+    /// `execute` is recorded and available from `timings`. When
+    /// timing is disabled, no time is measured.
+    pub fn set_timing(&mut self, enabled: bool) {
+        self.timing = enabled;
+    }
+
+    /// The durations recorded while timing.
+    pub fn timings(&self) -> Timings {
+        self.timings.get()
+    }
+
+    fn start_timing(&self) -> Option<Instant> {
+        if self.timing {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    fn record_timing(
+        &self,
+        start: Option<Instant>,
+        field: fn(&mut Timings) -> &mut Option<Duration>,
+    ) {
+        if let Some(start) = start {
+            let mut timings = self.timings.get();
+            *field(&mut timings) = Some(start.elapsed());
+            self.timings.set(timings);
         }
     }
 
+    /// Store a `value` in the context of the parser under `key`.
+    ///
+    /// The context is kept from one command to the next, so
+    /// a handler can store a value here for use by a later
+    /// command with a parameter which takes its value
+    /// `from_context`.
+    pub fn set_context(&self, key: &str, value: &str) {
+        self.context.borrow_mut().insert(key.to_string(), value.to_string());
+    }
+
+    /// Get the value stored in the context of the parser
+    /// under `key`.
+    pub fn context(&self, key: &str) -> Option<String> {
+        self.context.borrow().get(key).cloned()
+    }
+
+    /// All of the values stored in the context of the parser,
+    /// by key.
+    ///
+    /// These may be given to `set_context_values` on another
+    /// parser, such as when a new parser is made for each line
+    /// of input.
+    pub fn context_values(&self) -> HashMap<String, String> {
+        self.context.borrow().clone()
+    }
+
+    /// Replace the values stored in the context of the parser.
+    pub fn set_context_values(&mut self, values: HashMap<String, String>) {
+        self.context = RefCell::new(values);
+    }
+
+    /// Enable or disable experimental parameters. When disabled,
+    /// experimental parameters neither match nor complete.
+    ///
+    /// This defaults to `false`.
+    pub fn enable_experimental(&mut self, enabled: bool) {
+        self.experimental = enabled;
+    }
+
+    /// Enable or disable counting how often each command
+    /// is executed.
+    ///
+    /// A command is counted when its handler is run by
+    /// `execute` or `try_execute`, so commands without a handler
+    /// are not counted.
+    ///
+    /// The counts are available from `usage_counts` and can be
+    /// given to `set_usage_stats` to rank completions.
+    pub fn record_usage(&mut self, enabled: bool) {
+        self.recording_usage = enabled;
+    }
+
+    /// How many times each command has been executed, by name,
+    /// while recording usage.
+    ///
+    /// These counts are kept from one command to the next until
+    /// they are cleared with `clear_usage_counts`.
+    pub fn usage_counts(&self) -> HashMap<String, u64> {
+        self.usage_counts.borrow().clone()
+    }
+
+    /// Clear the counts of how often each command has been executed.
+    pub fn clear_usage_counts(&mut self) {
+        self.usage_counts.borrow_mut().clear();
+    }
+
+    /// Supply how often each command or parameter has been used,
+    /// by name. This is used by `complete_ranked`.
+    pub fn set_usage_stats(&mut self, usage_stats: HashMap<String, u64>) {
+        self.usage_stats = usage_stats;
+    }
+
     /// Given an optional token, get the possible valid completions
     /// for the current parser state.
     ///
     /// Possible completions are successors of the current node which
     /// are not `hidden`, are `acceptable`, and which match the token,
-    /// if one has been provided.
+    /// if one has been provided. Hidden nodes are also completed
+    /// after `set_show_hidden`.
     ///
     /// Nodes may customize the `Complete` trait to customize the
     /// [`Completion`] and [`CompletionOption`]s which are generated
@@ -190,7 +518,8 @@ impl<'text> Parser<'text> {
     /// Each valid successor node will have one [`Completion`] in the
     /// result vector. Each [`Completion`] will have one or more
     /// [`CompletionOption`] for each valid way that the value may be
-    /// entered.
+    /// entered. Those with the highest priority are given first,
+    /// and those of equal priority are ordered by name.
     ///
     /// ```
     /// use commands::parser::{Command, CommandTree, Parser};
@@ -228,23 +557,291 @@ impl<'text> Parser<'text> {
     ///
     /// [`Completion`]: struct.Completion.html
     /// [`CompletionOption`]: struct.CompletionOption.html
-    pub fn complete(&self, token: Option<Token<'text>>) -> Vec<Completion> {
-        self.current_node
-            .successors()
+    pub fn complete(&self, token: Option<Token<'text>>) -> Vec<Completion<'text>> {
+        self.completable_nodes(token)
+            .iter()
+            .map(|n| self.complete_node(n, token))
+            .collect::<Vec<_>>()
+    }
+
+    /// Get the possible valid completions, as with `complete`, with
+    /// the most relevant first.
+    ///
+    /// Completions are ranked by the priority of their node, then
+    /// by how often the node has been used according to the usage
+    /// stats given to `set_usage_stats`, and then by how much of
+    /// their name the token covers, so that `l` ranks `ls` ahead of
+    /// `list`. Completions which are otherwise equal are ordered by
+    /// name.
+    pub fn complete_ranked(&self, token: Option<Token<'text>>) -> Vec<Completion<'text>> {
+        let prefix_len = token.map_or(0, |t| t.text.chars().count());
+        let mut nodes = self.completable_nodes(token)
+            .into_iter()
+            .map(|n| {
+                let frequency = self.usage_stats.get(&n.node().name).cloned().unwrap_or(0);
+                // The token covers `matched` of the `len` characters of the name.
+                let (matched, len) = match token {
+                    Some(t) if n.matches_by_name() && n.node().is_named(t.text) => (1, 1),
+                    Some(_) if n.matches_by_name() => (prefix_len, n.node().name.chars().count()),
+                    _ => (0, 1),
+                };
+                ((n.node().priority, frequency, matched, len), n)
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|&(a, _), &(b, _)| {
+            (b.0, b.1, b.2 * a.3).cmp(&(a.0, a.1, a.2 * b.3))
+        });
+        nodes.into_iter().map(|(_, n)| self.complete_node(&n, token)).collect()
+    }
+
+    /// Get the possible completions, as with `complete`, matching
+    /// the names of commands and parameters to the token as a
+    /// subsequence rather than as a prefix, so that `shw` completes
+    /// `show`.
+    ///
+    /// Completions are ordered by how well their name or one of their
+    /// aliases matches the token, as scored by [`fuzzy_score`], best
+    /// first, and then by priority and name. Nodes which aren't
+    /// matched by name, such as parameter values, follow them. The
+    /// options of each completion are those which match the token.
+    ///
+    /// This only affects completion, as `parse` still requires
+    /// names to be given in full or as a prefix.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("set"));
+    /// let parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("shw") {
+    ///     let completions = parser.complete_fuzzy(Some(tokens[0]));
+    ///     assert_eq!(completions.len(), 1);
+    ///     assert_eq!(completions[0].options[0].option_string, "show");
+    /// }
+    /// ```
+    ///
+    /// [`fuzzy_score`]: ../util/fn.fuzzy_score.html
+    pub fn complete_fuzzy(&self, token: Option<Token<'text>>) -> Vec<Completion<'text>> {
+        let t = match token {
+            Some(t) => t,
+            None => return self.complete(None),
+        };
+        let score = |name: &str| {
+            if self.case_insensitive {
+                fuzzy_score(&t.text.to_ascii_lowercase(), &name.to_ascii_lowercase())
+            } else {
+                fuzzy_score(t.text, name)
+            }
+        };
+        let mut nodes = self.successors()
+            .iter()
+            .filter(|n| {
+                (self.show_hidden || !n.node().hidden) && self.may_follow(n) &&
+                    n.acceptable(self, n)
+            })
+            .filter_map(|n| {
+                let node = n.node();
+                let score = if n.matches_by_name() {
+                    let names = Some(&node.name).into_iter().chain(&node.aliases);
+                    names.filter_map(|name| score(name)).max()?
+                } else if n.matches(self, t) {
+                    0
+                } else {
+                    return None;
+                };
+                Some(((Reverse(score), Reverse(node.priority), node.name.clone()), n))
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+        nodes
+            .into_iter()
+            .map(|(_, n)| {
+                if !n.matches_by_name() {
+                    return self.complete_node(n, token);
+                }
+                let mut completion = self.complete_node(n, None);
+                completion.options.retain(|o| o.complete && score(&o.option_string).is_some());
+                completion.token = token;
+                completion.replace_span = t.span();
+                completion
+            })
+            .collect()
+    }
+
+    /// Get the possible valid completions for the current parser
+    /// state, as with `complete`, serialized as JSON.
+    ///
+    /// The result is an array with an object for each completion
+    /// option, giving its `text`, the `kind` of node that it
+    /// completes (`command`, `flag`, `named` or `parameter`),
+    /// the `help` text for that node and whether or not it is
+    /// `complete`.
+    ///
+    /// This requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn complete_json(&self, token: Option<Token<'text>>) -> String {
+        let mut candidates = vec![];
+        for node in self.completable_nodes(token) {
+            let kind = match *node {
+                Node::Command(_) | Node::Root(_) => "command",
+                Node::Parameter(ParameterNode { kind: ParameterKind::Flag, .. }) => "flag",
+                Node::ParameterName(_) => "named",
+                Node::Parameter(_) => "parameter",
+            };
+            let completion = self.complete_node(&node, token);
+            for option in completion.options {
+                candidates.push(CompletionCandidate {
+                    text: option.option_string,
+                    kind: kind,
+                    help: completion.help_text.clone(),
+                    complete: option.complete,
+                });
+            }
+        }
+        serde_json::to_string(&candidates).expect("Serializing completions can not fail.")
+    }
+
+    /// Complete the `node`, taking case insensitivity and the
+    /// completion of aliases into account.
+    fn complete_node(&self, node: &Rc<Node>, token: Option<Token<'text>>) -> Completion<'text> {
+        let mut completion = self.complete_name(node, token);
+        completion.hidden = node.node().hidden;
+        if let Node::Command(ref command) = **node {
+            if self.complete_aliases {
+                let aliases = command
+                    .node
+                    .aliases
+                    .iter()
+                    .filter(|a| match token {
+                        Some(t) => self.matches_prefix(a, t.text),
+                        None => true,
+                    })
+                    .map(|a| CompletionOption::new(a.clone(), true));
+                completion.options.extend(aliases);
+            }
+        }
+        completion
+    }
+
+    fn complete_name(&self, node: &Rc<Node>, token: Option<Token<'text>>) -> Completion<'text> {
+        match token {
+            Some(t) if self.case_insensitive && node.matches_by_name() => {
+                let mut completion = node.complete(None);
+                completion.token = token;
+                completion.replace_span = t.span();
+                completion
+                    .options
+                    .retain(|o| is_prefix_ignoring_case(&o.option_string, t.text));
+                completion
+            }
+            _ => node.complete(token),
+        }
+    }
+
+    /// Whether `prefix` is a prefix of `text`, taking case
+    /// insensitivity into account.
+    fn matches_prefix(&self, text: &str, prefix: &str) -> bool {
+        if self.case_insensitive {
+            is_prefix_ignoring_case(text, prefix)
+        } else {
+            is_prefix(text, prefix)
+        }
+    }
+
+    /// Whether `text` matches the name of `node`, taking prefix
+    /// matching and case insensitivity into account.
+    fn matches_name(&self, node: &TreeNode, text: &str) -> bool {
+        match (self.prefix_matching, self.case_insensitive) {
+            (true, true) => node.matches_name_ignoring_case(text),
+            (true, false) => node.matches_name(text),
+            (false, true) => node.is_named_ignoring_case(text),
+            (false, false) => node.is_named(text),
+        }
+    }
+
+    /// To be a possible completion, the node should not be hidden,
+    /// unless hidden nodes are being shown, it should be acceptable,
+    /// and if there's a token, it should be a valid match for the
+    /// node.
+    fn completable_nodes(&self, token: Option<Token<'text>>) -> Vec<Rc<Node>> {
+        let mut nodes = self.successors()
             .iter()
             .filter(|n| {
-                // To be a possible completion, the node should not be
-                // hidden, it should be acceptable, and if there's a token,
-                // it should be a valid match for the node.
-                !n.node().hidden && n.acceptable(self, n) &&
+                (self.show_hidden || !n.node().hidden) && self.may_follow(n) &&
+                    n.acceptable(self, n) &&
                     if let Some(t) = token {
                         n.matches(self, t)
                     } else {
                         true
                     }
             })
-            .map(|n| n.complete(token))
-            .collect::<Vec<_>>()
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_by_priority(&mut nodes);
+        nodes
+    }
+
+    /// Get the possible completions for a line of input, given
+    /// the byte offset of the cursor within the line as `point`.
+    ///
+    /// This is what shell completion scripts need. The text up to
+    /// the cursor is tokenized and the words before the word being
+    /// completed are parsed. If the cursor is within or at the end
+    /// of a word, that word is used to filter the completions.
+    /// Otherwise, all completions at that position are returned.
+    ///
+    /// The `replace_span` of each completion gives the bytes of the
+    /// line which its options should replace: the partial word when
+    /// there is one and otherwise an empty range at `point`.
+    ///
+    /// The parser is `reset` before the preceding words are parsed,
+    /// so the same parser may be used to complete each line. If the
+    /// text up to the cursor can not be tokenized, or the preceding
+    /// words can not be parsed, the failure is given as a
+    /// [`CommandError`].
+    ///
+    /// # Panics
+    ///
+    /// This panics if `point` is not on a character boundary within
+    /// the `line`.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("set"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// let comps = parser.complete_line("sh", 2).unwrap();
+    /// assert_eq!(comps.len(), 1);
+    /// assert_eq!(comps[0].options[0].option_string, "show");
+    /// assert_eq!(comps[0].replace_span, 0..2);
+    /// assert!(parser.complete_line("bogus ", 6).is_err());
+    /// ```
+    ///
+    /// [`CommandError`]: enum.CommandError.html
+    pub fn complete_line(
+        &mut self,
+        line: &'text str,
+        point: usize,
+    ) -> Result<Vec<Completion<'text>>, CommandError<'text>> {
+        self.reset();
+        let mut tokens = tokenize(&line[..point])?;
+        let partial = match tokens.last() {
+            Some(&Token { token_type: TokenType::Word, .. }) => tokens.pop(),
+            _ => None,
+        };
+        self.parse(tokens)?;
+        let replace_span = partial.map_or(point..point, |t| t.span());
+        let mut completions = self.complete(partial);
+        for completion in &mut completions {
+            completion.replace_span = replace_span.clone();
+        }
+        Ok(completions)
     }
 
     /// Parse a vector of tokens, advancing through the
@@ -264,178 +861,5002 @@ impl<'text> Parser<'text> {
     /// }
     /// ```
     pub fn parse(&mut self, tokens: Vec<Token<'text>>) -> Result<(), ParseError<'text>> {
-        for token in tokens {
+        let start = self.start_timing();
+        let result = self.parse_tokens(tokens);
+        self.record_timing(start, |t| &mut t.parse);
+        result
+    }
+
+    /// Parse a vector of tokens as with `parse`, recovering from
+    /// tokens which don't match any node, and return every error
+    /// which was found. A clean parse gives no errors.
+    ///
+    /// This is useful for checking a whole script at once. See
+    /// `set_recovery` for how recovery works. An error which can't
+    /// be recovered from, such as an ambiguous match, ends the
+    /// parse and is the last error given.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// let errors = parser.parse_recovering(tokenize("show bogus").unwrap());
+    /// assert_eq!(errors.len(), 1);
+    /// ```
+    pub fn parse_recovering(&mut self, tokens: Vec<Token<'text>>) -> Vec<ParseError<'text>> {
+        let recovering = self.recovering;
+        self.recovering = true;
+        let result = self.parse(tokens);
+        self.recovering = recovering;
+        let mut errors = self.recovered_errors.clone();
+        errors.extend(result.err());
+        errors
+    }
+
+    fn parse_tokens(&mut self, tokens: Vec<Token<'text>>) -> Result<(), ParseError<'text>> {
+        for (i, &token) in tokens.iter().enumerate() {
             match token.token_type {
                 TokenType::Whitespace => {}
-                TokenType::Word => try!(self.advance(token)),
+                TokenType::Word if self.bind_greedy(&tokens[i..]) => break,
+                TokenType::Word => match self.advance(token) {
+                    Err(err @ ParseError::NoMatches(..)) if self.recovering => {
+                        self.unmatched.push(token);
+                        self.recovered_errors.push(err);
+                    }
+                    Err(err) => {
+                        let rest = tokens[i..].iter().filter(|t| t.token_type == TokenType::Word);
+                        self.unmatched.extend(rest);
+                        return Err(err);
+                    }
+                    Ok(()) => {}
+                },
             }
         }
+        self.bind_defaults();
         Ok(())
     }
 
-    /// Parse a single token, advancing through the node hierarchy.
-    pub fn advance(&mut self, token: Token<'text>) -> Result<(), ParseError<'text>> {
-        let matches = self.current_node
-            .successors()
-            .iter()
-            .filter(|n| n.acceptable(self, n) && n.matches(self, token))
-            .cloned()
-            .collect::<Vec<_>>();
-        match matches.len() {
-            1 => {
-                let matching_node = &matches[0];
-                matching_node.accept(self, token, matching_node);
-                self.current_node = Rc::clone(matching_node);
-                self.nodes.push(Rc::clone(matching_node));
-                self.tokens.push(token);
-                Ok(())
-            }
-            0 => {
-                Err(ParseError::NoMatches(
-                    token,
-                    self.current_node
-                        .successors()
-                        .iter()
-                        .filter(|n| n.acceptable(self, n))
-                        .cloned()
-                        .collect::<Vec<_>>(),
-                ))
+    /// Bind the `rest` of the tokens verbatim to the greedy parameter
+    /// of the accepted command, if it has one which hasn't been given.
+    ///
+    /// If a `--` is still to come, the tokens before it are parsed
+    /// as usual and only those after it are bound.
+    fn bind_greedy(&mut self, rest: &[Token<'text>]) -> bool {
+        let greedy = self.last_command().and_then(|c| match **c {
+            Node::Command(ref command) => {
+                command
+                    .parameters
+                    .iter()
+                    .find(|p| match ***p {
+                        Node::Parameter(ref p) => p.greedy,
+                        _ => false,
+                    })
+                    .cloned()
             }
-            _ => Err(ParseError::AmbiguousMatch(token, matches)),
+            _ => None,
+        });
+        let greedy = match greedy {
+            Some(ref greedy) if greedy.acceptable(self, greedy) => Rc::clone(greedy),
+            _ => return false,
+        };
+        let words = rest.iter().filter(|t| t.token_type == TokenType::Word);
+        if !self.options_terminated && words.clone().any(|t| t.text == "--") {
+            return false;
         }
+        let value = rest.iter().map(|t| t.text).collect::<String>();
+        self.parameters.insert(greedy.node().name.clone(), value.trim_end().to_string());
+        self.last_good_position += words.count();
+        self.current_node = Rc::clone(&greedy);
+        self.nodes.push(greedy);
+        self.tokens.push(rest[0]);
+        true
     }
 
-    /// Execute the command that has been accepted by the parser.
+    /// Parse as much of `tokens` as possible without failing.
+    ///
+    /// Parsing stops at the first token which can't be accepted.
+    /// The [`SuggestResult`] gives the tokens which were accepted,
+    /// those which were not, and the completions from the point
+    /// at which parsing stopped. This is useful for offering
+    /// assistance while a command line is being edited.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("bogus") {
+    ///     let suggestion = parser.suggest(tokens);
+    ///     assert!(suggestion.matched.is_empty());
+    ///     assert_eq!(suggestion.completions.len(), 1);
+    /// }
+    /// ```
     ///
-    /// * XXX: This should be returning a Result probably.
-    pub fn execute(&self) {
-        if !self.commands.is_empty() {
-            unimplemented!();
-            // self.commands[0].execute(self)
+    /// [`SuggestResult`]: struct.SuggestResult.html
+    pub fn suggest(&mut self, tokens: Vec<Token<'text>>) -> SuggestResult<'text> {
+        let mut tokens = tokens.into_iter();
+        let mut unmatched = vec![];
+        for token in &mut tokens {
+            if token.token_type == TokenType::Word && self.advance(token).is_err() {
+                unmatched.push(token);
+                break;
+            }
+        }
+        unmatched.extend(tokens.filter(|t| t.token_type == TokenType::Word));
+        SuggestResult {
+            matched: self.tokens.clone(),
+            unmatched: unmatched,
+            completions: self.complete(None),
         }
     }
 
-    /// Verify that the parser is in a valid state with
-    /// respect to having accepted a command and all
-    /// required parameters.
-    pub fn verify(&self) -> Result<(), VerifyError> {
-        if let Some(&Node::Command(ref command)) = self.commands.first().map(|n| &**n) {
-            for expected in &command.parameters {
-                if let Node::Parameter(ref param) = **expected {
-                    let name = &param.node.name;
-                    if param.required && !self.parameters.contains_key(name) {
-                        return Err(VerifyError::MissingParameter(name.clone()));
+    /// Take the values of any parameters of the accepted command
+    /// which weren't given from the context, or failing that, from
+    /// their default values.
+    fn bind_defaults(&mut self) {
+        let command = self.last_command().cloned();
+        if let Some(command) = command {
+            if let Node::Command(ref command) = *command {
+                for parameter in &command.parameters {
+                    if let Node::Parameter(ref p) = **parameter {
+                        if self.parameters.contains_key(&p.node.name) {
+                            continue;
+                        }
+                        let value = p.context_key
+                            .as_ref()
+                            .and_then(|key| self.context(key))
+                            .or_else(|| p.default_value.clone());
+                        if let Some(value) = value {
+                            self.parameters.insert(p.node.name.clone(), value);
+                        }
                     }
-                } else {
-                    unreachable!();
                 }
             }
-            Ok(())
-        } else {
-            Err(VerifyError::NoCommandAccepted)
         }
     }
-}
-
-/// Errors that calling `parse` on the `Parser` can raise.
-#[derive(Clone)]
-pub enum ParseError<'text> {
-    /// There were no matches for the token.
-    NoMatches(Token<'text>, Vec<Rc<Node>>),
-    /// There was more than 1 possible match for the token.
-    AmbiguousMatch(Token<'text>, Vec<Rc<Node>>),
-}
 
-impl<'text> fmt::Debug for ParseError<'text> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            ParseError::NoMatches(token, _) => write!(f, "NoMatches({:?}, ...)", token),
-            ParseError::AmbiguousMatch(token, _) => write!(f, "AmbiguousMatch({:?}, ...)", token),
+    /// Parse a batch of commands separated by `;` tokens.
+    ///
+    /// Each command in the batch is parsed independently from the
+    /// root and the result of each is collected into a
+    /// [`BatchResult`], so that every error in the batch can be
+    /// reported at once. Once this returns, the parser holds the
+    /// state from parsing the last command in the batch.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("show; bogus") {
+    ///     let batch = parser.parse_all(tokens);
+    ///     assert_eq!(batch.segments.len(), 2);
+    ///     assert_eq!(batch.first_error().map(|(i, _)| i), Some(1));
+    /// }
+    /// ```
+    ///
+    /// [`BatchResult`]: struct.BatchResult.html
+    pub fn parse_all(&mut self, tokens: Vec<Token<'text>>) -> BatchResult<'text> {
+        let mut segments = vec![];
+        let mut start = 0;
+        for end in 0..tokens.len() + 1 {
+            let is_separator = match tokens.get(end) {
+                Some(t) => t.token_type == TokenType::Word && t.text == ";",
+                None => true,
+            };
+            if !is_separator {
+                continue;
+            }
+            let segment = tokens[start..end].to_vec();
+            if segment.iter().any(|t| t.token_type == TokenType::Word) {
+                self.reset();
+                segments.push(BatchSegment {
+                    tokens: start..end,
+                    result: self.parse(segment),
+                });
+            }
+            start = end + 1;
         }
+        BatchResult { segments: segments }
     }
-}
 
-impl<'text> Error for ParseError<'text> {
-    fn description(&self) -> &str {
-        match *self {
-            ParseError::NoMatches(_, _) => "No match.",
-            ParseError::AmbiguousMatch(_, _) => "Ambiguous match.",
-        }
+    /// Clear the state accumulated while parsing, keeping the
+    /// root of the tree and the configuration of the parser, so
+    /// that the parser can be reused for another line of input.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("set"));
+    /// let mut parser = Parser::new(tree.finalize());
+    /// for line in &["show", "set"] {
+    ///     parser.reset();
+    ///     parser.parse(tokenize(line).unwrap()).unwrap();
+    /// }
+    /// ```
+    pub fn reset(&mut self) {
+        self.current_node = Rc::clone(&self.root);
+        self.nodes.clear();
+        self.tokens.clear();
+        self.commands.clear();
+        self.parameters.clear();
+        self.repeated_values.clear();
+        self.last_good_position = 0;
+        self.near_misses.clear();
+        self.options_terminated = false;
+        self.trace.clear();
+        self.recovered_errors.clear();
+        self.unmatched.clear();
+    }
+
+    /// The most deeply nested command which has been matched, if
+    /// any.
+    ///
+    /// This is available whether or not `verify` succeeds, so that
+    /// help for the command can be given when it fails.
+    pub fn matched_command(&self) -> Option<&CommandNode> {
+        match **self.last_command()? {
+            Node::Command(ref command) => Some(command),
+            _ => None,
+        }
+    }
+
+    /// The names of the commands which have been matched, from
+    /// the top level command to the most deeply nested subcommand.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show").subcommand(Command::new("interface")));
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("sh int") {
+    ///     parser.parse(tokens).unwrap();
+    ///     assert_eq!(parser.command_path(), vec!["show", "interface"]);
+    /// }
+    /// ```
+    pub fn command_path(&self) -> Vec<&str> {
+        self.nodes
+            .iter()
+            .filter_map(|n| match **n {
+                Node::Command(ref command) => Some(command.node.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The span of each token which was matched during `parse`,
+    /// along with the node which it matched, such as for
+    /// highlighting the input.
+    ///
+    /// A named parameter gives the span of its name, matching the
+    /// `ParameterNameNode`, and the span of its value, matching the
+    /// `ParameterNode`. Tokens which didn't match are given by
+    /// `unmatched_spans`.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("  show") {
+    ///     parser.parse(tokens).unwrap();
+    ///     let spans = parser.matched_spans();
+    ///     assert_eq!(spans[0].0, 2..6);
+    ///     assert_eq!(spans[0].1.node().name, "show");
+    /// }
+    /// ```
+    pub fn matched_spans(&self) -> Vec<(Range<usize>, &Rc<Node>)> {
+        self.tokens.iter().map(|t| t.span()).zip(&self.nodes).collect()
+    }
+
+    /// The spans of the tokens which didn't match during `parse`.
+    ///
+    /// This gives the token which failed to match and those which
+    /// follow it or, when recovering, each token which was skipped.
+    pub fn unmatched_spans(&self) -> Vec<Range<usize>> {
+        self.unmatched.iter().map(|t| t.span()).collect()
+    }
+
+    /// The number of times that the parameter with the given `name`
+    /// was given, which is 0 when it wasn't given.
+    ///
+    /// This is useful with repeatable flags, where `-v -v -v`, or
+    /// `-vvv` when short clusters are enabled, gives a verbosity of 3.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, ParameterKind, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(
+    ///     Command::new("run")
+    ///         .parameter(Parameter::new("-v").kind(ParameterKind::Flag).repeatable(true)),
+    /// );
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("run -v -v") {
+    ///     parser.parse(tokens).unwrap();
+    ///     assert_eq!(parser.occurrences("-v"), 2);
+    /// }
+    /// ```
+    pub fn occurrences(&self, name: &str) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| match ***n {
+                Node::Parameter(ref p) => p.node.name == name,
+                _ => false,
+            })
+            .count()
+    }
+
+    /// Describe what may come next in the current parser state,
+    /// such as for showing a hint as the user types.
+    ///
+    /// Unlike `complete`, this gives the kinds of nodes which may
+    /// follow rather than the text which they may be completed to.
+    /// Hidden nodes are not included unless they are being shown
+    /// with `set_show_hidden`.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Expectation, Parser};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("set"));
+    /// let parser = Parser::new(tree.finalize());
+    /// let expectations = parser.expectations();
+    /// assert_eq!(
+    ///     expectations,
+    ///     vec![Expectation::Command(vec!["set".to_string(), "show".to_string()])]
+    /// );
+    /// assert_eq!(expectations[0].to_string(), "one of: set, show");
+    /// ```
+    pub fn expectations(&self) -> Vec<Expectation> {
+        let mut commands = vec![];
+        let mut flags = vec![];
+        let mut named = vec![];
+        let mut values = vec![];
+        for node in self.completable_nodes(None) {
+            let name = node.node().name.clone();
+            match *node {
+                Node::Command(_) => commands.push(name),
+                Node::ParameterName(_) => named.push(name),
+                Node::Parameter(ref p) if p.kind == ParameterKind::Flag => flags.push(name),
+                Node::Parameter(_) => values.push(Expectation::Value(name)),
+                Node::Root(_) => {}
+            }
+        }
+        let mut expectations = vec![];
+        if !commands.is_empty() {
+            expectations.push(Expectation::Command(commands));
+        }
+        if !flags.is_empty() {
+            expectations.push(Expectation::Flag(flags));
+        }
+        if !named.is_empty() {
+            expectations.push(Expectation::NamedParameter(named));
+        }
+        expectations.extend(values);
+        expectations
+    }
+
+    /// The values of the parameters which have been given, by the
+    /// name of each parameter.
+    ///
+    /// Parameters are given by their names as they were defined,
+    /// even when they were matched by an alias or an abbreviation.
+    /// The values of repeatable parameters are collected in the
+    /// order in which they were given. Flags have their own name as
+    /// their value, or the number of times that they were given if
+    /// they are repeatable.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, ParameterKind, ParameterValue,
+    ///                        Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(
+    ///     Command::new("tag")
+    ///         .parameter(Parameter::new("--name").kind(ParameterKind::Named).repeatable(true)),
+    /// );
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("tag --name a --name b") {
+    ///     parser.parse(tokens).unwrap();
+    ///     let values = parser.parameters();
+    ///     assert_eq!(
+    ///         values["--name"],
+    ///         ParameterValue::Multiple(vec!["a".to_string(), "b".to_string()])
+    ///     );
+    /// }
+    /// ```
+    pub fn parameters(&self) -> HashMap<String, ParameterValue> {
+        self.parameters
+            .iter()
+            .map(|(name, value)| {
+                let value = match self.repeated_values.get(name) {
+                    Some(values) => ParameterValue::Multiple(values.clone()),
+                    None => ParameterValue::Single(value.clone()),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Whether or not a `--` end of options marker has been seen
+    /// after a command.
+    ///
+    /// This lets a command know that the values following the marker
+    /// were explicitly given as values rather than as options. Once
+    /// options have been terminated, only simple parameters are
+    /// matched and completed, so that values like `--weird` are not
+    /// taken to be flags.
+    pub fn options_terminated(&self) -> bool {
+        self.options_terminated
+    }
+
+    /// The commands which partially matched before parsing failed.
+    ///
+    /// When a command has been matched but a later token, such as
+    /// a parameter, fails to match, that command is recorded as a
+    /// near miss. This allows diagnostics to be specific about the
+    /// command which came closest to matching.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.command(Command::new("help"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("show bogus") {
+    ///     assert!(parser.parse(tokens).is_err());
+    ///     assert_eq!(parser.near_misses().len(), 1);
+    ///     assert_eq!(parser.near_misses()[0].node().name, "show");
+    /// }
+    /// ```
+    pub fn near_misses(&self) -> &[Rc<Node>] {
+        &self.near_misses
+    }
+
+    /// The message for an unknown command given to the `CommandTree`,
+    /// if `error` is a `ParseError::NoMatches` for the first word of
+    /// the command line.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    /// tree.unknown_command_message(Box::new(|text| format!("Unknown command '{}'.", text)));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("bogus") {
+    ///     let err = parser.parse(tokens).unwrap_err();
+    ///     assert_eq!(parser.unknown_command_message(&err).unwrap(),
+    ///                "Unknown command 'bogus'.");
+    /// }
+    /// ```
+    pub fn unknown_command_message(&self, error: &ParseError) -> Option<String> {
+        match (error, &*self.current_node) {
+            (ParseError::NoMatches(token, _), Node::Root(root)) => {
+                root.unknown_command_message.as_ref().map(|message| message(token.text))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a single token, advancing through the node hierarchy.
+    ///
+    /// If the parser is at the root and the token is a path alias,
+    /// each word of the aliased command path is matched in turn.
+    ///
+    /// After `set_equals_separator`, a `=` token following the name
+    /// of a named parameter is consumed as a separator, so that
+    /// `name = value` binds `value`.
+    ///
+    /// A token like `--name=value`, where `--name` is the name or an
+    /// alias of a named parameter, gives the parameter the value after
+    /// the first `=`. An empty value is a `ParseError::NoMatches`
+    /// whose only acceptable node is the parameter.
+    ///
+    /// The first `--` token after a command marks the end of options
+    /// and is consumed without being matched. Following tokens are
+    /// only matched to simple parameters. See [`options_terminated`].
+    /// Before a command has been matched, `--` is matched like any
+    /// other token.
+    ///
+    /// [`options_terminated`]: struct.Parser.html#method.options_terminated
+    pub fn advance(&mut self, token: Token<'text>) -> Result<(), ParseError<'text>> {
+        if token.text == "--" && !self.options_terminated && self.last_command().is_some() {
+            self.options_terminated = true;
+            self.last_good_position += 1;
+            return Ok(());
+        }
+        let current_node = Rc::clone(&self.current_node);
+        if let Node::ParameterName(ref name) = *current_node {
+            if self.equals_separator && token.text == "=" {
+                self.last_good_position += 1;
+                return Ok(());
+            }
+            let value_optional = match *name.parameter {
+                Node::Parameter(ref p) => p.value_optional,
+                _ => false,
+            };
+            if value_optional && token.text.starts_with('-') {
+                // The value was left out, so this token follows the command.
+                if let Some(command) = self.last_command().cloned() {
+                    self.current_node = command;
+                }
+            } else if value_optional || self.named_value_policy == NamedValuePolicy::NextNode {
+                self.skip_named_value(token);
+            }
+        }
+        if let Node::Root(ref root) = *current_node {
+            if let Some(path) = root.path_aliases.get(token.text) {
+                for word in path {
+                    self.advance_as(token, word)?;
+                }
+                self.last_good_position += 1;
+                return Ok(());
+            }
+        }
+        if let Some((name, value)) = self.split_named_value(token) {
+            self.advance_as(token, name)?;
+            if value.is_empty() {
+                let acceptable = match *self.current_node {
+                    Node::ParameterName(ref n) => vec![Rc::clone(&n.parameter)],
+                    _ => vec![],
+                };
+                return Err(ParseError::NoMatches(token, acceptable));
+            }
+            self.advance_as(token, value)?;
+            self.last_good_position += 1;
+            return Ok(());
+        }
+        if self.is_short_cluster(token) {
+            // Each flag is matched as its own token, so that an error
+            // refers to the flag within the cluster which failed.
+            let start = token.location.start;
+            for (n, (offset, c)) in token.text.char_indices().enumerate().skip(1) {
+                let at = SourceOffset::new(
+                    start.char + n,
+                    start.byte + offset,
+                    start.line,
+                    start.column + n,
+                );
+                let text = &token.text[offset..offset + c.len_utf8()];
+                let flag = Token::new(text, token.token_type, SourceLocation::new(at, at));
+                self.advance_as(flag, &format!("-{}", c))?;
+            }
+            self.last_good_position += 1;
+            return Ok(());
+        }
+        self.advance_as(token, token.text)?;
+        self.last_good_position += 1;
+        Ok(())
+    }
+
+    /// How far parsing got before failing.
+    ///
+    /// This is the number of word tokens which have been successfully
+    /// matched. When `parse` fails, it is also the index (counting only
+    /// word tokens) of the token which could not be matched, so that
+    /// the point where understanding broke down can be shown.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("show"));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("show bogus") {
+    ///     assert!(parser.parse(tokens).is_err());
+    ///     assert_eq!(parser.last_good_position(), 1);
+    /// }
+    /// ```
+    pub fn last_good_position(&self) -> usize {
+        self.last_good_position
+    }
+
+    /// Split a token like `--name=value` into the name and the value
+    /// when the name is that of a named parameter which may be given.
+    fn split_named_value(&self, token: Token<'text>) -> Option<(&'text str, &'text str)> {
+        if self.options_terminated || !token.text.starts_with('-') {
+            return None;
+        }
+        let text = token.text;
+        let equals = text.find('=')?;
+        let name = Token::new(&text[..equals], token.token_type, token.location);
+        let names_parameter = self.successors().iter().any(|n| match **n {
+            Node::ParameterName(_) => n.acceptable(self, n) && n.matches(self, name),
+            _ => false,
+        });
+        if names_parameter {
+            Some((&text[..equals], &text[equals + 1..]))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `value` is the given `choice`, taking case
+    /// insensitivity into account.
+    fn is_choice(&self, choice: &str, value: &str) -> bool {
+        if self.case_insensitive {
+            choice.eq_ignore_ascii_case(value)
+        } else {
+            choice == value
+        }
+    }
+
+    /// Whether `node` may be matched given whether or not options
+    /// have been terminated by `--`, after which only simple
+    /// parameters are matched.
+    fn may_follow(&self, node: &Node) -> bool {
+        match *node {
+            _ if !self.options_terminated => true,
+            Node::Parameter(ref p) => p.kind == ParameterKind::Simple,
+            _ => false,
+        }
+    }
+
+    /// Whether the parameter with the given `name` was given on the
+    /// command line, rather than taking a default value.
+    fn was_given(&self, name: &str) -> bool {
+        self.nodes.iter().any(|n| match **n {
+            Node::Parameter(ref p) => p.node.name == name,
+            _ => false,
+        })
+    }
+
+    /// The command which was most recently accepted.
+    fn last_command(&self) -> Option<&Rc<Node>> {
+        self.nodes.iter().rev().find(|n| matches!(***n, Node::Command(_)))
+    }
+
+    /// The nodes which may follow the current node.
+    ///
+    /// Once a parameter has been given, the successors of the
+    /// command are used so that its other parameters may follow.
+    fn successors(&self) -> &[Rc<Node>] {
+        self.successors_of().successors()
+    }
+
+    /// The node whose successors may follow the current node.
+    fn successors_of(&self) -> &Rc<Node> {
+        if let Node::Parameter(_) = *self.current_node {
+            if let Some(command) = self.last_command() {
+                return command;
+            }
+        }
+        &self.current_node
+    }
+
+    /// The successor which `word` names exactly, if there is only
+    /// one and an exact match wins. This is looked up by name so
+    /// that the successors needn't all be tried.
+    fn exact_successor(&self, word: Token) -> Option<Rc<Node>> {
+        if !self.exact_wins || self.case_insensitive {
+            return None;
+        }
+        let mut exact = self.successors_of()
+            .node()
+            .successors_named(word.text)
+            .filter(|n| self.may_follow(n) && n.acceptable(self, n) && n.matches(self, word));
+        match (exact.next(), exact.next()) {
+            (Some(node), None) => Some(Rc::clone(node)),
+            _ => None,
+        }
+    }
+
+    /// Leave the value of the named parameter which was just given
+    /// unset if `token` matches the name of a node which may follow
+    /// the command instead.
+    fn skip_named_value(&mut self, token: Token<'text>) {
+        let command = self.last_command().cloned();
+        if let Some(command) = command {
+            let names_node = command.successors().iter().any(|n| {
+                n.matches_by_name() && n.acceptable(self, n) && n.matches(self, token)
+            });
+            if names_node {
+                self.current_node = command;
+            }
+        }
+    }
+
+    /// Whether `token` is a cluster of short flags, like `-vx`,
+    /// which should be expanded because it doesn't match as is.
+    fn is_short_cluster(&self, token: Token<'text>) -> bool {
+        if !self.short_clusters || self.options_terminated || !token.text.starts_with('-') ||
+            token.text.starts_with("--") || token.text.chars().count() <= 2
+        {
+            return false;
+        }
+        let first = token.text.chars().nth(1).map(|c| format!("-{}", c)).unwrap_or_default();
+        let first = Token::new(&first, token.token_type, token.location);
+        let by_name = |text: Token| {
+            self.successors()
+                .iter()
+                .any(|n| n.matches_by_name() && n.acceptable(self, n) && n.matches(self, text))
+        };
+        !by_name(token) && by_name(first)
+    }
+
+    /// Advance through the node hierarchy, matching `token` as
+    /// though its text were `text`.
+    fn advance_as(&mut self, token: Token<'text>, text: &str) -> Result<(), ParseError<'text>> {
+        let word = Token::new(text, token.token_type, token.location);
+        let mut matches = match self.exact_successor(word) {
+            Some(node) => vec![node],
+            None => self.successors()
+                .iter()
+                .filter(|n| self.may_follow(n) && n.acceptable(self, n) && n.matches(self, word))
+                .cloned()
+                .collect::<Vec<_>>(),
+        };
+        if matches.len() > 1 && self.exact_wins {
+            let exact = matches
+                .iter()
+                .filter(|n| {
+                    n.matches_by_name() &&
+                        if self.case_insensitive {
+                            n.node().is_named_ignoring_case(text)
+                        } else {
+                            n.node().is_named(text)
+                        }
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if exact.len() == 1 {
+                matches = exact;
+            }
+        }
+        // Otherwise, the nodes with the highest priority are preferred.
+        if matches.len() > 1 {
+            let highest = matches.iter().map(|n| n.node().priority).max();
+            matches.retain(|n| Some(n.node().priority) == highest);
+        }
+        // Simple parameters are positional, so when a value could be
+        // any of them, it belongs to the first one declared.
+        let all_simple = matches.iter().all(|n| match **n {
+            Node::Parameter(ref p) => p.kind == ParameterKind::Simple,
+            _ => false,
+        });
+        if all_simple {
+            matches.truncate(1);
+        }
+        match matches.len() {
+            1 => {
+                // A lazily defined command is replaced by its definition.
+                let matching_node = &match *matches[0] {
+                    Node::Command(CommandNode { lazy: Some(ref lazy), .. }) => {
+                        Rc::clone(lazy.definition())
+                    }
+                    _ => Rc::clone(&matches[0]),
+                };
+                matching_node.accept(self, word, matching_node);
+                let canonical = match **matching_node {
+                    Node::ParameterName(ref name) => name.parameter.node(),
+                    ref node => node.node(),
+                };
+                // A named parameter is reported when its name is matched.
+                let named_value = match **matching_node {
+                    Node::Parameter(ref p) => p.kind == ParameterKind::Named,
+                    _ => false,
+                };
+                if canonical.deprecated && !named_value {
+                    if let Some(ref handler) = self.deprecation_handler {
+                        handler(&canonical.name);
+                    }
+                }
+                if self.tracing {
+                    self.trace.push(TraceEvent {
+                        token: token.text.to_string(),
+                        canonical: canonical.name.clone(),
+                    });
+                }
+                self.current_node = Rc::clone(matching_node);
+                self.nodes.push(Rc::clone(matching_node));
+                self.tokens.push(token);
+                Ok(())
+            }
+            0 => {
+                // The command that was matched before this failure,
+                // if any, is the branch which came closest to matching.
+                let near_miss = self.last_command().cloned();
+                if let Some(command) = near_miss {
+                    self.near_misses.push(command);
+                }
+                let mut acceptable = self.successors()
+                    .iter()
+                    .filter(|n| self.may_follow(n) && n.acceptable(self, n))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                sort_by_priority(&mut acceptable);
+                Err(ParseError::NoMatches(token, acceptable))
+            }
+            _ => {
+                sort_by_priority(&mut matches);
+                Err(ParseError::AmbiguousMatch(token, matches))
+            }
+        }
+    }
+
+    /// The value of the parameter `name`, parsed according to its
+    /// `ValueType`.
+    ///
+    /// This gives `None` if the parameter wasn't given or its value
+    /// isn't valid, which `verify` reports.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, Parser, TypedValue, ValueType};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(
+    ///     Command::new("sleep").parameter(Parameter::new("seconds").value_type(ValueType::Float)),
+    /// );
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("sleep 0.5") {
+    ///     parser.parse(tokens).unwrap();
+    ///     assert_eq!(parser.typed_value("seconds"), Some(TypedValue::Float(0.5)));
+    /// }
+    /// ```
+    pub fn typed_value(&self, name: &str) -> Option<TypedValue> {
+        let value = self.parameters.get(name)?;
+        self.parameter_node(name)?.value_type.parse(value)
+    }
+
+    /// The value of the parameter `name` as an integer.
+    ///
+    /// This gives `None` if the parameter wasn't given, or if it
+    /// doesn't have a `ValueType::Integer` value.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, Parser, ValueType};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(
+    ///     Command::new("head").parameter(Parameter::new("lines").value_type(ValueType::Integer)),
+    /// );
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("head 20") {
+    ///     parser.parse(tokens).unwrap();
+    ///     assert_eq!(parser.get_i64("lines"), Some(20));
+    /// }
+    /// ```
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        match self.typed_value(name)? {
+            TypedValue::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value of the parameter `name` as a boolean.
+    ///
+    /// A flag is `true` when it was given, unless it was negated.
+    /// Otherwise, this gives `None` if the parameter wasn't given,
+    /// or if it doesn't have a `ValueType::Boolean` value.
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        let value = self.parameters.get(name)?;
+        let param = self.parameter_node(name)?;
+        if param.kind == ParameterKind::Flag {
+            return Some(value != "false");
+        }
+        match param.value_type.parse(value)? {
+            TypedValue::Boolean(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value of the parameter `name` as it was given.
+    ///
+    /// This gives `None` if the parameter wasn't given. Unlike the
+    /// other getters, this doesn't depend on the `ValueType` of the
+    /// parameter.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(|value| value.as_str())
+    }
+
+    /// The parameter `name` of the command which has been accepted.
+    fn parameter_node(&self, name: &str) -> Option<&ParameterNode> {
+        match **self.last_command()? {
+            Node::Command(ref command) => command
+                .parameters
+                .iter()
+                .filter_map(|n| match **n {
+                    Node::Parameter(ref p) if p.node.name == name => Some(p),
+                    _ => None,
+                })
+                .next(),
+            _ => None,
+        }
+    }
+
+    /// Reconstruct a command line from what has been accepted by
+    /// the parser.
+    ///
+    /// Commands and the names of parameters are given in full,
+    /// even when they were abbreviated or given by an alias, and
+    /// values are quoted as needed, so that the command line can
+    /// be reused, such as by copying it from a UI.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parameter, ParameterKind, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(
+    ///     Command::new("echo").parameter(Parameter::new("--text").kind(ParameterKind::Named)),
+    /// );
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("ec --t 'two words'") {
+    ///     parser.parse(tokens).unwrap();
+    ///     assert_eq!(parser.to_command_line(), "echo --text \"two words\"");
+    /// }
+    /// ```
+    pub fn to_command_line(&self) -> String {
+        let mut words: Vec<String> = vec![];
+        for (node, token) in self.nodes.iter().zip(&self.tokens) {
+            match **node {
+                Node::Parameter(ref p) if p.greedy => {
+                    words.push(self.parameters[&p.node.name].clone());
+                }
+                Node::Parameter(ref p) if p.kind != ParameterKind::Flag => {
+                    words.push(quote(&token.value()));
+                }
+                Node::ParameterName(ref name) => words.push(name.parameter.node().name.clone()),
+                ref node => words.push(node.node().name.clone()),
+            }
+        }
+        words.join(" ")
+    }
+
+    /// Execute the command that has been accepted by the parser.
+    ///
+    /// This invokes the handler of the command and returns its
+    /// status. If no command has been accepted, the status is
+    /// `ExecStatus::Continue`.
+    ///
+    /// If the handler fails, the error is written to standard error
+    /// and the status is `ExecStatus::Continue`. Use `try_execute`
+    /// to handle the errors of handlers given by
+    /// `Command::try_handler` instead.
+    pub fn execute(&self) -> ExecStatus {
+        match self.try_execute() {
+            Ok(status) => status,
+            Err(error) => {
+                eprintln!("{}", error);
+                ExecStatus::Continue
+            }
+        }
+    }
+
+    /// Execute the command that has been accepted by the parser,
+    /// as with `execute`, giving an [`ExecError`] if its handler
+    /// fails.
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, Parser};
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("fail").try_handler(|_| Err("Out of cheese.".to_string())));
+    /// let mut parser = Parser::new(tree.finalize());
+    /// if let Ok(tokens) = tokenize("fail") {
+    ///     parser.parse(tokens).unwrap();
+    ///     let error = parser.try_execute().unwrap_err();
+    ///     assert_eq!(error.to_string(), "fail: Out of cheese.");
+    /// }
+    /// ```
+    ///
+    /// [`ExecError`]: struct.ExecError.html
+    pub fn try_execute(&self) -> Result<ExecStatus, ExecError> {
+        let start = self.start_timing();
+        let result = match self.commands.last().map(|n| &**n) {
+            Some(&Node::Command(CommandNode { handler: Some(ref handler), ref node, .. })) => {
+                if self.recording_usage {
+                    *self.usage_counts.borrow_mut().entry(node.name.clone()).or_insert(0) += 1;
+                }
+                handler(self).map_err(|message| ExecError {
+                    command: node.name.clone(),
+                    message: message,
+                })
+            }
+            _ => Ok(ExecStatus::Continue),
+        };
+        self.record_timing(start, |t| &mut t.execute);
+        result
+    }
+
+    /// Tokenize, parse, verify and execute a line of input.
+    ///
+    /// This is a convenience for the common case of handling a
+    /// single line of input, such as within a REPL. Failure at any
+    /// stage is reported as a [`CommandError`].
+    ///
+    /// ```
+    /// use commands::parser::{Command, CommandTree, ExecStatus, Parser};
+    ///
+    /// let mut tree = CommandTree::new();
+    /// tree.command(Command::new("quit").handler(|_| ExecStatus::Exit));
+    ///
+    /// let mut parser = Parser::new(tree.finalize());
+    /// match parser.run("quit") {
+    ///     Ok(ExecStatus::Exit) => {}
+    ///     _ => panic!("Expected to exit."),
+    /// }
+    /// ```
+    ///
+    /// [`CommandError`]: enum.CommandError.html
+    pub fn run(&mut self, input: &'text str) -> Result<ExecStatus, CommandError<'text>> {
+        let tokens = tokenize(input)?;
+        self.parse(tokens)?;
+        self.verify()?;
+        Ok(self.try_execute()?)
+    }
+
+    /// Verify that the parser is in a valid state with
+    /// respect to having accepted a command and all
+    /// required parameters.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let start = self.start_timing();
+        let result = self.verify_commands();
+        self.record_timing(start, |t| &mut t.verify);
+        result
+    }
+
+    /// Each command on the path is verified, starting from the root,
+    /// so that the parameters of a command are still checked when
+    /// one of its subcommands has been given.
+    fn verify_commands(&self) -> Result<(), VerifyError> {
+        if self.commands.is_empty() {
+            return Err(VerifyError::NoCommandAccepted);
+        }
+        for command in &self.nodes {
+            if let Node::Command(ref command) = **command {
+                self.verify_command(command)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_command(&self, command: &CommandNode) -> Result<(), VerifyError> {
+        let missing = command
+            .parameters
+            .iter()
+            .filter_map(|n| match **n {
+                Node::Parameter(ref p) if p.required => Some(&p.node.name),
+                _ => None,
+            })
+            .filter(|name| !self.parameters.contains_key(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(VerifyError::MissingParameter(missing));
+        }
+        for expected in &command.parameters {
+            if let Node::Parameter(ref param) = **expected {
+                let name = &param.node.name;
+                let occurrences = self.nodes.iter().filter(|n| *n == expected).count();
+                if let Some(ref negated) = param.negates {
+                    let both = occurrences > 0 &&
+                        self.nodes.iter().any(|n| match **n {
+                            Node::Parameter(ref p) => &p.node.name == negated,
+                            _ => false,
+                        });
+                    if both {
+                        return Err(VerifyError::ConflictingParameters(
+                            negated.clone(),
+                            name.clone(),
+                        ));
+                    }
+                }
+                if occurrences > 0 {
+                    if let Some(required) =
+                        param.requires.iter().find(|r| !self.was_given(r))
+                    {
+                        return Err(VerifyError::MissingDependency(
+                            name.clone(),
+                            required.clone(),
+                        ));
+                    }
+                    if let Some(conflict) =
+                        param.conflicts_with.iter().find(|c| self.was_given(c))
+                    {
+                        return Err(VerifyError::ConflictingParameters(
+                            name.clone(),
+                            conflict.clone(),
+                        ));
+                    }
+                }
+                if occurrences > 0 && occurrences < param.min_occurs {
+                    return Err(VerifyError::TooFewOccurrences(
+                        name.clone(),
+                        param.min_occurs,
+                    ));
+                }
+                match param.occurrence_limit() {
+                    Some(limit) if occurrences > limit => {
+                        return Err(VerifyError::TooManyOccurrences(name.clone(), limit));
+                    }
+                    _ => {}
+                }
+                if let (Some(fs), Some(value)) = (self.file_system.as_ref(),
+                                                  self.parameters.get(name))
+                {
+                    if !param.path_validation.check(&**fs, Path::new(value)) {
+                        return Err(VerifyError::InvalidPath(
+                            name.clone(),
+                            param.path_validation,
+                        ));
+                    }
+                }
+                // The values of repeatable and list parameters are
+                // each checked, rather than just the last of them.
+                let values = match self.repeated_values.get(name) {
+                    Some(values) => values.iter().collect::<Vec<_>>(),
+                    None => self.parameters.get(name).into_iter().collect(),
+                };
+                for value in values {
+                    if param.value_type.parse(value).is_none() {
+                        return Err(VerifyError::InvalidValue(
+                            name.clone(),
+                            param.value_type,
+                            value.clone(),
+                        ));
+                    }
+                    let omitted = param.value_optional && value.is_empty();
+                    if !param.choices.is_empty() && !omitted &&
+                        !param.choices.iter().any(|c| self.is_choice(c, value))
+                    {
+                        return Err(VerifyError::InvalidChoice(
+                            name.clone(),
+                            value.clone(),
+                            param.choices.clone(),
+                        ));
+                    }
+                }
+            } else {
+                unreachable!();
+            }
+        }
+        let mut filled: Vec<(&String, &String)> = vec![];
+        for parameter in &command.parameters {
+            if let Node::Parameter(ParameterNode { slot: Some(ref slot), ref node, .. }) =
+                **parameter
+            {
+                if !self.was_given(&node.name) {
+                    continue;
+                }
+                if let Some(&(_, first)) = filled.iter().find(|&&(s, _)| s == slot) {
+                    return Err(VerifyError::ConflictingParameters(
+                        first.clone(),
+                        node.name.clone(),
+                    ));
+                }
+                filled.push((slot, &node.name));
+            }
+        }
+        for group in &command.exclusive_groups {
+            let mut given = group.iter().filter(|name| self.was_given(name));
+            if let (Some(first), Some(second)) = (given.next(), given.next()) {
+                return Err(VerifyError::ConflictingParameters(
+                    first.clone(),
+                    second.clone(),
+                ));
+            }
+        }
+        if let Some(ref token) = command.required_token {
+            if !self.parameters.contains_key(token) {
+                return Err(VerifyError::ConfirmationRequired(token.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of a parameter as given by `Parser::parameters`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterValue {
+    /// The value of a parameter which isn't repeatable.
+    Single(String),
+    /// The values of a repeatable parameter, in the order in
+    /// which they were given.
+    Multiple(Vec<String>),
+}
+
+/// A kind of node which may come next, as given by
+/// `Parser::expectations`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// One of the commands with these names.
+    Command(Vec<String>),
+    /// One of the flags with these names.
+    Flag(Vec<String>),
+    /// One of the named parameters with these names, which are
+    /// followed by their values.
+    NamedParameter(Vec<String>),
+    /// The value of the parameter with this name.
+    Value(String),
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Expectation::Command(ref names) => write!(f, "one of: {}", names.join(", ")),
+            Expectation::Flag(ref names) => write!(f, "a flag: {}", names.join(", ")),
+            Expectation::NamedParameter(ref names) => {
+                write!(f, "a parameter: {}", names.join(", "))
+            }
+            Expectation::Value(ref name) => write!(f, "the value for {}", name),
+        }
+    }
+}
+
+/// The result of parsing one command within a batch.
+pub struct BatchSegment<'text> {
+    /// The range of the tokens given to `parse_all` which
+    /// make up this command.
+    pub tokens: Range<usize>,
+    /// The result of parsing this command.
+    pub result: Result<(), ParseError<'text>>,
+}
+
+/// The results of parsing a batch of commands with `parse_all`.
+pub struct BatchResult<'text> {
+    /// The result for each command in the batch, in order.
+    pub segments: Vec<BatchSegment<'text>>,
+}
+
+impl<'text> BatchResult<'text> {
+    /// The index of the first command in the batch which
+    /// failed to parse, along with its error.
+    pub fn first_error(&self) -> Option<(usize, &ParseError<'text>)> {
+        self.all_errors().into_iter().next()
+    }
+
+    /// The index and error of every command in the batch
+    /// which failed to parse.
+    pub fn all_errors(&self) -> Vec<(usize, &ParseError<'text>)> {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.result.as_ref().err().map(|e| (i, e)))
+            .collect()
+    }
+}
+
+/// How a token following the name of a named parameter is
+/// treated when it also matches the name of another node, as
+/// with `--name show` when `show` is a flag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NamedValuePolicy {
+    /// The token is the value of the named parameter.
+    Value,
+    /// The token is matched as the other node, leaving the
+    /// named parameter without a value.
+    NextNode,
+}
+
+/// Called with the name of a deprecated command or parameter
+/// when it is matched by the `Parser`.
+pub type DeprecationHandler = Box<dyn Fn(&str)>;
+
+/// Invoked with the `Parser` when the command that it belongs
+/// to is executed. A handler which fails gives a message saying
+/// why.
+///
+/// The parameters which were given may be found via
+/// [`Parser::parameters`].
+///
+/// [`Parser::parameters`]: struct.Parser.html#method.parameters
+pub type CommandHandler = Rc<dyn Fn(&Parser) -> Result<ExecStatus, String>>;
+
+/// Invoked with the text typed so far when completing the value
+/// of a parameter, to provide values to offer.
+///
+/// This is given to `Parameter::complete_with`.
+pub type ValueCompleter = Rc<dyn Fn(&str) -> Vec<String>>;
+
+/// The best effort structure found by `suggest`.
+pub struct SuggestResult<'text> {
+    /// The tokens which were accepted by the parser.
+    pub matched: Vec<Token<'text>>,
+    /// The tokens from the first which could not be accepted
+    /// to the end of the input.
+    pub unmatched: Vec<Token<'text>>,
+    /// The completions at the point where parsing stopped.
+    pub completions: Vec<Completion<'text>>,
+}
+
+/// The durations recorded by the `Parser` while timing.
+///
+/// Each duration is that of the most recent call, and is `None`
+/// if there has been no call while timing was enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timings {
+    /// The time taken by `parse`.
+    pub parse: Option<Duration>,
+    /// The time taken by `verify`.
+    pub verify: Option<Duration>,
+    /// The time taken by `execute`.
+    pub execute: Option<Duration>,
+}
+
+/// An event recorded by the `Parser` while tracing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    /// The text of the token as it was typed.
+    pub token: String,
+    /// The canonical name of the node which the token matched. For
+    /// the name of a named parameter, this is the name of the
+    /// parameter, even when the token matched an alias.
+    pub canonical: String,
+}
+
+/// Errors that calling `parse` on the `Parser` can raise.
+#[derive(Clone)]
+pub enum ParseError<'text> {
+    /// There were no matches for the token.
+    NoMatches(Token<'text>, Vec<Rc<Node>>),
+    /// There was more than 1 possible match for the token.
+    AmbiguousMatch(Token<'text>, Vec<Rc<Node>>),
+}
+
+impl<'text> fmt::Debug for ParseError<'text> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::NoMatches(token, _) => write!(f, "NoMatches({:?}, ...)", token),
+            ParseError::AmbiguousMatch(token, _) => write!(f, "AmbiguousMatch({:?}, ...)", token),
+        }
+    }
+}
+
+impl<'text> Error for ParseError<'text> {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::NoMatches(_, _) => "No match.",
+            ParseError::AmbiguousMatch(_, _) => "Ambiguous match.",
+        }
+    }
+}
+
+/// The `Display` output summarizes the token which failed to match
+/// and the nodes which could have matched it:
+///
+/// * `NoMatches`: `No match for 'bogus' (2 options).`
+/// * `AmbiguousMatch`: `Ambiguous match for 's': set, show.`
+impl<'text> fmt::Display for ParseError<'text> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ParseError::NoMatches(token, ref acceptable) => {
+                let plural = if acceptable.len() == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "No match for '{}' ({} option{}).",
+                    token.text,
+                    acceptable.len(),
+                    plural
+                )
+            }
+            ParseError::AmbiguousMatch(token, ref matches) => {
+                let names = matches
+                    .iter()
+                    .map(|n| n.node().name.as_str())
+                    .collect::<Vec<_>>();
+                write!(f, "Ambiguous match for '{}': {}.", token.text, names.join(", "))
+            }
+        }
+    }
+}
+
+/// Sort `nodes` with the highest priority first, and then by
+/// name, so that candidates are given in a predictable order.
+fn sort_by_priority(nodes: &mut [Rc<Node>]) {
+    nodes.sort_by(|a, b| {
+        let (a, b) = (a.node(), b.node());
+        b.priority.cmp(&a.priority).then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+/// Errors that calling `verify` on the `Parser` can raise.
+#[derive(Clone, Debug)]
+pub enum VerifyError {
+    /// No command has been accepted by the parser.
+    NoCommandAccepted,
+    /// Required parameters are missing. This gives the names of
+    /// all of the missing parameters.
+    MissingParameter(Vec<String>),
+    /// The value of a path parameter failed its validation.
+    InvalidPath(String, PathValidation),
+    /// The value of a parameter can't be parsed as its type. This
+    /// gives the name of the parameter, its type and the value.
+    InvalidValue(String, ValueType, String),
+    /// The value of a parameter isn't one of its choices, such as
+    /// when only a prefix of a choice was given. This gives the name
+    /// of the parameter, the value and the choices.
+    InvalidChoice(String, String, Vec<String>),
+    /// The command is dangerous and the token which confirms
+    /// that it should be executed was not given.
+    ConfirmationRequired(String),
+    /// A parameter was given fewer times than it must be. This
+    /// gives the name of the parameter and the fewest times.
+    TooFewOccurrences(String, usize),
+    /// A parameter was given more times than it may be. This
+    /// gives the name of the parameter and the most times.
+    TooManyOccurrences(String, usize),
+    /// Parameters which contradict each other, such as a flag and
+    /// its negation or two members of an exclusive group or of a
+    /// choice, were both given. This gives their names.
+    ConflictingParameters(String, String),
+    /// A parameter was given without a parameter that it requires.
+    /// This gives the names of the parameter and what it requires.
+    MissingDependency(String, String),
+}
+
+impl Error for VerifyError {
+    fn description(&self) -> &str {
+        match *self {
+            VerifyError::NoCommandAccepted => "No command has been accepted by the parser.",
+            VerifyError::MissingParameter(_) => "Required parameters are missing.",
+            VerifyError::InvalidPath(_, _) => "A path parameter is not valid.",
+            VerifyError::InvalidValue(_, _, _) => "A parameter is not of the right type.",
+            VerifyError::InvalidChoice(_, _, _) => "A parameter is not one of its choices.",
+            VerifyError::ConfirmationRequired(_) => {
+                "This command must be confirmed before it is executed."
+            }
+            VerifyError::TooFewOccurrences(_, _) => "A parameter is given too few times.",
+            VerifyError::TooManyOccurrences(_, _) => "A parameter is given too many times.",
+            VerifyError::ConflictingParameters(_, _) => "Conflicting parameters are given.",
+            VerifyError::MissingDependency(_, _) => "A parameter requires another parameter.",
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            VerifyError::InvalidValue(ref name, value_type, ref value) => {
+                write!(f, "The value '{}' of {} must be {}.", value, name, value_type.description())
+            }
+            VerifyError::InvalidChoice(ref name, ref value, ref choices) => {
+                write!(
+                    f,
+                    "The value '{}' of {} must be one of: {}.",
+                    value,
+                    name,
+                    choices.join(", ")
+                )
+            }
+            VerifyError::MissingParameter(ref names) => {
+                write!(f, "Missing required parameters: {}.", names.join(", "))
+            }
+            VerifyError::TooFewOccurrences(ref name, min) => {
+                write!(f, "{} must be given at least {} times.", name, min)
+            }
+            VerifyError::TooManyOccurrences(ref name, max) => {
+                write!(f, "{} may be given at most {} times.", name, max)
+            }
+            VerifyError::ConflictingParameters(ref first, ref second) => {
+                write!(f, "{} and {} may not both be given.", first, second)
+            }
+            VerifyError::MissingDependency(ref name, ref required) => {
+                write!(f, "{} may only be given along with {}.", name, required)
+            }
+            _ => self.description().fmt(f),
+        }
+    }
+}
+
+/// A single completion option as serialized by `complete_json`.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct CompletionCandidate {
+    text: String,
+    kind: &'static str,
+    help: String,
+    complete: bool,
+}
+
+/// The status of a command after it has been executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExecStatus {
+    /// The command has completed and further input may be processed.
+    Continue,
+    /// The command has requested that no further input be processed,
+    /// such as when exiting a REPL.
+    Exit,
+}
+
+/// The failure of the handler of a command, as given by
+/// `try_execute` on the `Parser`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecError {
+    /// The name of the command which failed.
+    pub command: String,
+    /// The message given by the handler.
+    pub message: String,
+}
+
+impl Error for ExecError {}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}: {}", self.command, self.message)
+    }
+}
+
+/// Errors that calling `run` on the `Parser` can raise.
+///
+/// This unifies the errors from each stage of handling a line
+/// of input.
+#[derive(Clone, Debug)]
+pub enum CommandError<'text> {
+    /// The input could not be tokenized.
+    Tokenizer(TokenizerError),
+    /// The tokens could not be parsed.
+    Parse(ParseError<'text>),
+    /// The parsed command was not valid.
+    Verify(VerifyError),
+    /// The handler of the command failed.
+    Exec(ExecError),
+}
+
+impl<'text> CommandError<'text> {
+    /// Render the error along with the line of the `input` that
+    /// it occurred on, with the offending part underlined by
+    /// carets:
+    ///
+    /// ```text
+    /// No match.
+    /// show bogus
+    ///      ^^^^^
+    /// ```
+    ///
+    /// The `input` must be the text that was given to `run`. If
+    /// the error doesn't refer to a part of the input, then only
+    /// the error is rendered.
+    pub fn render_with_source(&self, input: &str) -> String {
+        let span = match self.span(input) {
+            Some(span) => span,
+            None => return self.to_string(),
+        };
+        let line_start = input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[span.start..]
+            .find('\n')
+            .map_or(input.len(), |i| span.start + i);
+        let column = input[line_start..span.start].chars().count();
+        let width = input[span.start..span.end.min(line_end)].chars().count();
+        format!(
+            "{}\n{}\n{}{}",
+            self,
+            &input[line_start..line_end],
+            " ".repeat(column),
+            "^".repeat(width.max(1))
+        )
+    }
+
+    /// The byte range of the `input` that this error refers to.
+    fn span(&self, input: &str) -> Option<Range<usize>> {
+        let token = match *self {
+            CommandError::Parse(ParseError::NoMatches(token, _)) |
+            CommandError::Parse(ParseError::AmbiguousMatch(token, _)) => token,
+            CommandError::Tokenizer(ref error) => {
+                let offset = error.offset().unwrap_or(input.len());
+                let len = input[offset..].chars().next().map_or(0, |c| c.len_utf8());
+                return Some(offset..offset + len);
+            }
+            CommandError::Verify(_) | CommandError::Exec(_) => return None,
+        };
+        Some(token.span())
+    }
+}
+
+impl<'text> From<TokenizerError> for CommandError<'text> {
+    fn from(error: TokenizerError) -> Self {
+        CommandError::Tokenizer(error)
+    }
+}
+
+impl<'text> From<ParseError<'text>> for CommandError<'text> {
+    fn from(error: ParseError<'text>) -> Self {
+        CommandError::Parse(error)
+    }
+}
+
+impl<'text> From<VerifyError> for CommandError<'text> {
+    fn from(error: VerifyError) -> Self {
+        CommandError::Verify(error)
+    }
+}
+
+impl<'text> From<ExecError> for CommandError<'text> {
+    fn from(error: ExecError) -> Self {
+        CommandError::Exec(error)
+    }
+}
+
+impl<'text> Error for CommandError<'text> {}
+
+impl<'text> fmt::Display for CommandError<'text> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            CommandError::Tokenizer(ref error) => error.fmt(f),
+            CommandError::Parse(ref error) => error.fmt(f),
+            CommandError::Verify(ref error) => error.fmt(f),
+            CommandError::Exec(ref error) => error.fmt(f),
+        }
     }
 }
 
-impl<'text> fmt::Display for ParseError<'text> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.description().fmt(f)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokenizer::{tokenize, tokenize_with_options, TokenizerOptions};
+
+    #[test]
+    #[should_panic]
+    fn verify_signals_no_command() {
+        let root = CommandTree::new().finalize();
+        let parser = Parser::new(root);
+        match parser.verify() {
+            Err(VerifyError::NoCommandAccepted) => panic!(),
+            _ => {}
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn parse_signals_no_matches() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        let mut parser = Parser::new(tree.finalize());
+        if let Ok(tokens) = tokenize("h") {
+            match parser.parse(tokens) {
+                Err(ParseError::NoMatches(_, _)) => panic!(),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn parse_signals_ambiguous_match() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set"));
+        let mut parser = Parser::new(tree.finalize());
+        if let Ok(tokens) = tokenize("s") {
+            match parser.parse(tokens) {
+                Err(ParseError::AmbiguousMatch(_, _)) => panic!(),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn parameter_choices_from_iter() {
+        let colors = vec!["red", "green", "blue"]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("paint").parameter(
+            Parameter::new("color").choices_from_iter(colors),
+        ));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("paint green") {
+            assert!(parser.parse(tokens).is_ok());
+            assert_eq!(parser.parameters["color"], "green");
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("paint purple") {
+            match parser.parse(tokens) {
+                Err(ParseError::NoMatches(_, _)) => {}
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn path_alias_parses_as_full_path() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").parameter(
+            Parameter::new("interface").kind(ParameterKind::Flag),
+        ));
+        tree.path_alias("sh-int", &["show", "interface"]);
+        let root = tree.finalize();
+
+        let mut full = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("show interface") {
+            assert!(full.parse(tokens).is_ok());
+        }
+        let mut aliased = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("sh-int") {
+            assert!(aliased.parse(tokens).is_ok());
+        }
+        assert_eq!(aliased.nodes.len(), 2);
+        assert!(aliased.nodes == full.nodes);
+        assert_eq!(aliased.parameters, full.parameters);
+    }
+
+    #[test]
+    fn last_good_position_after_failure() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").parameter(
+            Parameter::new("interface").kind(ParameterKind::Flag),
+        ));
+        let mut parser = Parser::new(tree.finalize());
+        assert_eq!(parser.last_good_position(), 0);
+        if let Ok(tokens) = tokenize("show interface bogus") {
+            assert!(parser.parse(tokens).is_err());
+        }
+        assert_eq!(parser.last_good_position(), 2);
+    }
+
+    fn help_symbols(parameters: Vec<Parameter>) -> Vec<String> {
+        let mut command = Command::new("show");
+        for parameter in parameters {
+            command = command.parameter(parameter);
+        }
+        let mut tree = CommandTree::new();
+        tree.command(command);
+        let root = tree.finalize();
+        let command = &root.successors()[0];
+        command
+            .successors()
+            .iter()
+            .map(|n| n.node().help_symbol.clone())
+            .collect()
+    }
+
+    #[test]
+    fn help_symbols_for_flags() {
+        let symbols = help_symbols(vec![
+            Parameter::new("--all").kind(ParameterKind::Flag),
+            Parameter::new("--brief")
+                .kind(ParameterKind::Flag)
+                .required(true),
+        ]);
+        assert_eq!(symbols, vec!["[--all]", "--brief"]);
+    }
+
+    #[test]
+    fn help_symbols_for_named_parameters() {
+        let symbols = help_symbols(vec![
+            Parameter::new("--color").kind(ParameterKind::Named),
+            Parameter::new("--size")
+                .kind(ParameterKind::Named)
+                .required(true),
+        ]);
+        assert_eq!(symbols, vec!["[--color <color>]", "--size <size>"]);
+    }
+
+    #[test]
+    fn help_symbols_for_simple_parameters() {
+        let symbols = help_symbols(vec![
+            Parameter::new("name").required(true),
+            Parameter::new("count"),
+            Parameter::new("files").repeatable(true),
+        ]);
+        assert_eq!(symbols, vec!["<name>", "[count]", "[files]..."]);
+    }
+
+    #[test]
+    fn run_executes_handler() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Exit));
+        tree.command(Command::new("set").handler(|_| ExecStatus::Continue));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.run("show").unwrap(), ExecStatus::Exit);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run("bogus") {
+            Err(CommandError::Parse(ParseError::NoMatches(_, _))) => {}
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run("show \"") {
+            Err(CommandError::Tokenizer(TokenizerError::UnclosedDoubleQuote(5))) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn command_see_also() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").see_also(&["set", "help"]));
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+        match *root.successors()[0] {
+            Node::Command(ref command) => assert_eq!(command.see_also(), ["set", "help"]),
+            _ => panic!(),
+        }
+        match *root.successors()[1] {
+            Node::Command(ref command) => assert!(command.see_also().is_empty()),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn named_parameter_with_equals_separator() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("set").parameter(
+            Parameter::new("--name").kind(ParameterKind::Named),
+        ));
+        let root = tree.finalize();
+        let options = TokenizerOptions {
+            equals_separator: true,
+            ..Default::default()
+        };
+
+        for input in &["set --name = value", "set --name=value"] {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.set_equals_separator(true);
+            if let Ok(tokens) = tokenize_with_options(input, options) {
+                assert!(parser.parse(tokens).is_ok());
+                assert_eq!(parser.parameters["--name"], "value");
+            }
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_equals_separator(true);
+        if let Ok(tokens) = tokenize_with_options("set --name \"=\"", options) {
+            assert!(parser.parse(tokens).is_ok());
+            assert_eq!(parser.parameters["--name"], "=");
+        }
+
+        // Without the option, a `=` token is a value.
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("set --name =").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--name"], "=");
+    }
+
+    #[test]
+    fn all_flags_across_commands() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("name")),
+        );
+        tree.command(Command::new("set").parameter(
+            Parameter::new("--force").kind(ParameterKind::Flag),
+        ));
+        tree.command(Command::new("help"));
+        let root = tree.finalize();
+        if let Node::Root(ref root) = *root {
+            let flags = root.all_flags()
+                .iter()
+                .map(|&(ref path, flag)| (path.join(" "), flag.node.name.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                flags,
+                vec![
+                    ("show".to_string(), "--all".to_string()),
+                    ("set".to_string(), "--force".to_string()),
+                ]
+            );
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn near_miss_records_partially_matched_command() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").parameter(
+            Parameter::new("--brief").kind(ParameterKind::Flag),
+        ));
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("bogus") {
+            assert!(parser.parse(tokens).is_err());
+        }
+        assert!(parser.near_misses().is_empty());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("show --brief bogus") {
+            assert!(parser.parse(tokens).is_err());
+        }
+        assert_eq!(parser.near_misses().len(), 1);
+        assert!(parser.near_misses()[0] == root.successors()[0]);
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn prefix_matching_respects_graphemes() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("cafe\u{301}"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("caf") {
+            assert!(parser.parse(tokens).is_ok());
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("cafe") {
+            assert!(parser.parse(tokens).is_err());
+        }
+    }
+
+    #[test]
+    fn options_terminated_only_after_marker() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("rm").parameter(Parameter::new("file")));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("rm file.txt") {
+            assert!(parser.parse(tokens).is_ok());
+        }
+        assert!(!parser.options_terminated());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        if let Ok(tokens) = tokenize("rm -- file.txt") {
+            assert!(parser.parse(tokens).is_ok());
+        }
+        assert!(parser.options_terminated());
+        assert_eq!(parser.parameters["file"], "file.txt");
+
+        // Before a command, `--` is not an end of options marker.
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.parse(tokenize("-- rm file.txt").unwrap()) {
+            Err(ParseError::NoMatches(token, _)) => assert_eq!(token.text, "--"),
+            _ => panic!("expected -- not to match"),
+        }
+        assert!(!parser.options_terminated());
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("--"));
+        let mut parser = Parser::new(tree.finalize());
+        parser.parse(tokenize("--").unwrap()).unwrap();
+        assert!(!parser.options_terminated());
+        assert_eq!(parser.command_path(), vec!["--"]);
+    }
+
+    #[test]
+    fn complete_line_within_word() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("shutdown"));
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let comps = parser.complete_line("show", 2).unwrap();
+        assert_eq!(comps.len(), 2);
+        assert_eq!(comps[0].options[0].option_string, "show");
+        assert_eq!(comps[1].options[0].option_string, "shutdown");
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.complete_line("show", 4).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn complete_line_at_word_boundary() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--brief").kind(ParameterKind::Flag)),
+        );
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.complete_line("", 0).unwrap().len(), 2);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let comps = parser.complete_line("show --all", 5).unwrap();
+        assert_eq!(comps.len(), 2);
+        assert_eq!(comps[0].help_symbol, "[--all]");
+        assert_eq!(comps[1].help_symbol, "[--brief]");
+
+        // The parser is reset for each line, and failures are reported.
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.complete_line("show --all ", 11).unwrap().len(), 1);
+        assert_eq!(parser.complete_line("show ", 5).unwrap().len(), 2);
+        match parser.complete_line("bogus ", 6) {
+            Err(CommandError::Parse(ParseError::NoMatches(token, _))) => {
+                assert_eq!(token.text, "bogus")
+            }
+            _ => panic!("expected bogus not to match"),
+        }
+        match parser.complete_line("show \"--all", 11) {
+            Err(CommandError::Tokenizer(_)) => {}
+            _ => panic!("expected the unterminated quote to fail to tokenize"),
+        }
+        assert_eq!(parser.complete_line("", 0).unwrap().len(), 2);
+    }
+
+    struct MockFileSystem {
+        files: Vec<&'static str>,
+        dirs: Vec<&'static str>,
+    }
+
+    impl FileSystem for MockFileSystem {
+        fn exists(&self, path: &Path) -> bool {
+            self.is_file(path) || self.is_dir(path)
+        }
+
+        fn is_file(&self, path: &Path) -> bool {
+            self.files.iter().any(|f| Path::new(f) == path)
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            self.dirs.iter().any(|d| Path::new(d) == path)
+        }
+    }
+
+    fn verify_path(validation: PathValidation, input: &str) -> Result<(), VerifyError> {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("open")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("path").path(validation)),
+        );
+        let mut parser = Parser::new(tree.finalize());
+        parser.set_file_system(Box::new(MockFileSystem {
+            files: vec!["notes.txt"],
+            dirs: vec!["docs"],
+        }));
+        parser.parse(tokenize(input).unwrap()).unwrap();
+        parser.verify()
+    }
+
+    #[test]
+    fn path_validation_none() {
+        assert!(verify_path(PathValidation::None, "open missing").is_ok());
+    }
+
+    #[test]
+    fn path_validation_must_exist() {
+        assert!(verify_path(PathValidation::MustExist, "open notes.txt").is_ok());
+        assert!(verify_path(PathValidation::MustExist, "open docs").is_ok());
+        match verify_path(PathValidation::MustExist, "open missing") {
+            Err(VerifyError::InvalidPath(ref name, PathValidation::MustExist)) => {
+                assert_eq!(name, "path")
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn path_validation_must_be_file() {
+        assert!(verify_path(PathValidation::MustBeFile, "open notes.txt").is_ok());
+        assert!(verify_path(PathValidation::MustBeFile, "open docs").is_err());
+        assert!(verify_path(PathValidation::MustBeFile, "open missing").is_err());
+    }
+
+    #[test]
+    fn path_validation_must_be_dir() {
+        assert!(verify_path(PathValidation::MustBeDir, "open docs").is_ok());
+        assert!(verify_path(PathValidation::MustBeDir, "open notes.txt").is_err());
+        assert!(verify_path(PathValidation::MustBeDir, "open missing").is_err());
+    }
+
+    #[test]
+    fn path_validation_without_file_system() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("open")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("path").path(PathValidation::MustExist)),
+        );
+        let mut parser = Parser::new(tree.finalize());
+        parser.parse(tokenize("open missing").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn complete_json_candidates() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").help("Show things."));
+        tree.command(Command::new("set").help("Set things."));
+        let mut parser = Parser::new(tree.finalize());
+
+        let json = parser.complete_json(None);
+        assert!(json.contains(r#"{"text":"show","kind":"command","help":"Show things.","complete":true}"#));
+        assert!(json.contains(r#"{"text":"set","kind":"command","help":"Set things.","complete":true}"#));
+
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert_eq!(parser.complete_json(None), "[]");
+    }
+
+    #[test]
+    fn tree_default_priority() {
+        let mut tree = CommandTree::new();
+        tree.default_priority(-5);
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set").priority(10));
+        let root = tree.finalize();
+        assert_eq!(root.successors()[0].node().priority, -5);
+        assert_eq!(root.successors()[1].node().priority, 10);
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        assert_eq!(tree.finalize().successors()[0].node().priority, PRIORITY_DEFAULT);
+    }
+
+    #[test]
+    fn empty_quoted_value_is_bound() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("set").parameter(
+            Parameter::new("--name").kind(ParameterKind::Named),
+        ));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize(r#"set --name """#).unwrap()).unwrap();
+        assert_eq!(parser.parameters.get("--name").map(|v| v.as_str()), Some(""));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("set").unwrap()).unwrap();
+        assert_eq!(parser.parameters.get("--name"), None);
+    }
+
+    #[test]
+    fn trace_records_alias_and_abbreviation() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").parameter(
+            Parameter::new("--color")
+                .kind(ParameterKind::Named)
+                .alias("--colour"),
+        ));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("sh --colour red").unwrap()).unwrap();
+        assert!(parser.trace().is_empty());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_trace(true);
+        parser.parse(tokenize("sh --colour red").unwrap()).unwrap();
+        let mapping = parser
+            .trace()
+            .iter()
+            .map(|e| (e.token.as_str(), e.canonical.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mapping,
+            vec![("sh", "show"), ("--colour", "--color"), ("red", "--color")]
+        );
+    }
+
+    #[test]
+    fn exact_match_wins_over_prefix() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("showtech"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.nodes[0] == root.successors()[0]);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("showt").unwrap()).unwrap();
+        assert!(parser.nodes[0] == root.successors()[1]);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_exact_wins(false);
+        match parser.parse(tokenize("show").unwrap()) {
+            Err(ParseError::AmbiguousMatch(_, ref matches)) => assert_eq!(matches.len(), 2),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_all_collects_errors() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set"));
+        let mut parser = Parser::new(tree.finalize());
+
+        let batch = parser.parse_all(tokenize("show; bogus; set").unwrap());
+        assert_eq!(batch.segments.len(), 3);
+        assert_eq!(batch.segments[0].tokens, 0..1);
+        assert_eq!(batch.segments[1].tokens, 2..4);
+        assert_eq!(batch.segments[2].tokens, 5..7);
+        assert!(batch.segments[0].result.is_ok());
+        assert!(batch.segments[2].result.is_ok());
+        match batch.first_error() {
+            Some((1, &ParseError::NoMatches(token, _))) => assert_eq!(token.text, "bogus"),
+            _ => panic!(),
+        }
+        assert_eq!(batch.all_errors().len(), 1);
+
+        let batch = parser.parse_all(tokenize("show; set;").unwrap());
+        assert_eq!(batch.segments.len(), 2);
+        assert!(batch.first_error().is_none());
+    }
+
+    #[test]
+    fn auto_short_alias_collision() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").auto_short_alias(true));
+        tree.command(Command::new("set").auto_short_alias(true));
+        let root = tree.finalize();
+        match *root {
+            Node::Root(ref root) => {
+                assert_eq!(
+                    root.warnings,
+                    vec![
+                        BuildWarning::ShortAliasUnavailable("set".to_string(), "s".to_string()),
+                    ]
+                );
+            }
+            _ => panic!(),
+        }
+        assert_eq!(root.successors()[0].node().aliases, vec!["s"]);
+        assert!(root.successors()[1].node().aliases.is_empty());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("s").unwrap()).unwrap();
+        assert_eq!(parser.nodes[0].node().name, "show");
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").auto_short_alias(true));
+        tree.command(Command::new("set").auto_short_alias(true));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::ShortAliasUnavailable("set".to_string(), "s".to_string()))
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").auto_short_alias(true));
+        tree.lazy_command("s", Box::new(|| Command::new("s")));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::ShortAliasUnavailable("show".to_string(), "s".to_string()))
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(
+                    Parameter::new("--verbose")
+                        .kind(ParameterKind::Flag)
+                        .auto_short_alias(true),
+                )
+                .parameter(Parameter::new("--version").kind(ParameterKind::Flag).short('v')),
+        );
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::ShortAliasUnavailable("--verbose".to_string(), "-v".to_string()))
+        );
+        let root = tree.finalize();
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show -v").unwrap()).unwrap();
+        assert!(parser.parameters.contains_key("--version"));
+    }
+
+    #[test]
+    fn auto_short_alias_for_parameters() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(
+                    Parameter::new("--verbose")
+                        .kind(ParameterKind::Flag)
+                        .auto_short_alias(true),
+                )
+                .parameter(
+                    Parameter::new("--interface")
+                        .kind(ParameterKind::Named)
+                        .auto_short_alias(true),
+                ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show -v").unwrap()).unwrap();
+        assert_eq!(parser.nodes[1].node().name, "--verbose");
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show -i eth0").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--interface"], "eth0");
+    }
+
+    #[test]
+    fn timings_only_when_enabled() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Continue));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        parser.verify().unwrap();
+        parser.execute();
+        assert_eq!(parser.timings(), Timings::default());
+
+        let mut parser = Parser::new(root);
+        parser.set_timing(true);
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.timings().parse.is_some());
+        assert!(parser.timings().verify.is_none());
+        parser.verify().unwrap();
+        parser.execute();
+        assert!(parser.timings().verify.is_some());
+        assert!(parser.timings().execute.is_some());
+    }
+
+    #[test]
+    fn unknown_command_message() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").parameter(Parameter::new("detail")
+                                                         .kind(ParameterKind::Flag)));
+        tree.unknown_command_message(Box::new(|text| {
+            format!("Unknown command '{}'. Type 'help'.", text)
+        }));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.parse(tokenize("bogus").unwrap()) {
+            Err(err @ ParseError::NoMatches(_, _)) => {
+                assert_eq!(parser.unknown_command_message(&err).unwrap(),
+                           "Unknown command 'bogus'. Type 'help'.");
+                if let ParseError::NoMatches(_, ref acceptable) = err {
+                    assert_eq!(acceptable.len(), 1);
+                }
+            }
+            _ => panic!(),
+        }
+
+        // Only the root uses the message.
+        let mut parser = Parser::new(root);
+        match parser.parse(tokenize("show bogus").unwrap()) {
+            Err(err @ ParseError::NoMatches(_, _)) => {
+                assert!(parser.unknown_command_message(&err).is_none());
+            }
+            _ => panic!(),
+        }
+    }
+
+    fn command_node(name: &str, successors: Vec<Rc<Node>>) -> Rc<Node> {
+        Rc::new(Node::Command(CommandNode::new(
+            name,
+            None,
+            false,
+            PRIORITY_DEFAULT,
+            successors,
+            None,
+            vec![],
+            &[],
+        )))
+    }
+
+    #[test]
+    fn grammar_with_subcommands() {
+        let name = ParameterNode::new(
+            "name",
+            None,
+            false,
+            PRIORITY_PARAMETER,
+            vec![],
+            false,
+            None,
+            ParameterKind::Simple,
+            true,
+        );
+        let brief = ParameterNode::new(
+            "--brief",
+            None,
+            false,
+            PRIORITY_DEFAULT,
+            vec![],
+            false,
+            None,
+            ParameterKind::Flag,
+            false,
+        );
+        let interface = command_node(
+            "interface",
+            vec![
+                Rc::new(Node::Parameter(name)),
+                Rc::new(Node::Parameter(brief)),
+            ],
+        );
+        let route = command_node("route", vec![]);
+
+        let show = command_node("show", vec![Rc::clone(&interface)]);
+        match *show {
+            Node::Command(ref show) => {
+                assert_eq!(show.grammar(), "show interface <name> [--brief]")
+            }
+            _ => panic!(),
+        }
+
+        let show = command_node("show", vec![interface, route]);
+        match *show {
+            Node::Command(ref show) => {
+                assert_eq!(show.grammar(), "show {interface <name> [--brief] | route}")
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parameter_from_context() {
+        fn select(parser: &Parser) -> ExecStatus {
+            parser.set_context("interface", "eth0");
+            ExecStatus::Continue
+        }
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("select").handler(select));
+        tree.command(
+            Command::new("show").handler(|_| ExecStatus::Continue).parameter(
+                Parameter::new("interface")
+                    .required(true)
+                    .from_context("interface"),
+            ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.run("select").unwrap();
+        assert_eq!(parser.context("interface"), Some("eth0".to_string()));
+        let context = parser.context_values();
+
+        // The context is kept when the parser is reset.
+        parser.reset();
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+        assert_eq!(parser.parameters()["interface"], ParameterValue::Single("eth0".into()));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_context_values(context.clone());
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+        assert_eq!(parser.get_str("interface"), Some("eth0"));
+
+        let mut parser = Parser::new(root);
+        parser.set_context_values(context);
+        parser.parse(tokenize("show eth1").unwrap()).unwrap();
+        assert_eq!(parser.get_str("interface"), Some("eth1"));
+    }
+
+    #[test]
+    fn suggest_after_garbage() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("interface").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("route").kind(ParameterKind::Flag)),
+        );
+        tree.command(Command::new("set"));
+        let mut parser = Parser::new(tree.finalize());
+
+        let suggestion = parser.suggest(tokenize("show bogus more").unwrap());
+        assert_eq!(
+            suggestion.matched.iter().map(|t| t.text).collect::<Vec<_>>(),
+            vec!["show"]
+        );
+        assert_eq!(
+            suggestion.unmatched.iter().map(|t| t.text).collect::<Vec<_>>(),
+            vec!["bogus", "more"]
+        );
+        assert_eq!(
+            suggestion
+                .completions
+                .iter()
+                .map(|c| c.help_symbol.as_str())
+                .collect::<Vec<_>>(),
+            vec!["[interface]", "[route]"]
+        );
+    }
+
+    #[test]
+    fn short_cluster_duplicates() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("-v").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("-x").kind(ParameterKind::Flag)),
+        );
+        tree.command(
+            Command::new("log")
+                .parameter(Parameter::new("-v").kind(ParameterKind::Flag).repeatable(true))
+                .parameter(Parameter::new("file").repeatable(true)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_short_clusters(true);
+        parser.parse(tokenize("show -vx").unwrap()).unwrap();
+        assert_eq!(parser.nodes.len(), 3);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_short_clusters(true);
+        match parser.parse(tokenize("show -vv").unwrap()) {
+            Err(ParseError::NoMatches(token, _)) => assert_eq!(token.span(), 7..8),
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_short_clusters(true);
+        parser.parse(tokenize("log -vv a.log -v b.log").unwrap()).unwrap();
+        assert_eq!(parser.parameters["-v"], "3");
+        // Repeatable values are collected rather than rejected.
+        assert_eq!(
+            parser.parameters()["file"],
+            ParameterValue::Multiple(vec!["a.log".to_string(), "b.log".to_string()])
+        );
+
+        // A value which doesn't start with a flag isn't expanded.
+        let mut parser = Parser::new(root);
+        parser.set_short_clusters(true);
+        parser.parse(tokenize("log -12").unwrap()).unwrap();
+        assert_eq!(parser.parameters["file"], "-12");
+    }
+
+    #[test]
+    fn deprecation_handler() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("display").deprecated(true).parameter(
+            Parameter::new("--all").kind(ParameterKind::Named).deprecated(true),
+        ));
+        let root = tree.finalize();
+
+        fn deprecation_handler(used: &Rc<RefCell<Vec<String>>>) -> DeprecationHandler {
+            let used = Rc::clone(used);
+            Box::new(move |name| used.borrow_mut().push(name.to_string()))
+        }
+        let used = Rc::new(RefCell::new(vec![]));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_deprecation_handler(deprecation_handler(&used));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(used.borrow().is_empty());
+
+        let mut parser = Parser::new(root);
+        parser.set_deprecation_handler(deprecation_handler(&used));
+        parser.parse(tokenize("display --all yes").unwrap()).unwrap();
+        assert_eq!(*used.borrow(), vec!["display", "--all"]);
+    }
+
+    #[test]
+    fn tree_diff() {
+        let mut before = CommandTree::new();
+        before.command(Command::new("show").parameter(Parameter::new("interface")));
+        before.command(Command::new("set"));
+
+        let mut after = CommandTree::new();
+        after.command(Command::new("show").parameter(
+            Parameter::new("interface").required(true),
+        ));
+        after.command(Command::new("set"));
+        after.command(Command::new("clear"));
+
+        assert!(before.diff(&before).is_empty());
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added, vec!["clear"]);
+        assert!(diff.removed.is_empty());
+        assert_eq!(
+            diff.changed,
+            vec![
+                CommandDiff {
+                    name: "show".to_string(),
+                    changed: false,
+                    added_parameters: vec![],
+                    removed_parameters: vec![],
+                    changed_parameters: vec!["interface".to_string()],
+                },
+            ]
+        );
+
+        let diff = after.diff(&before);
+        assert_eq!(diff.removed, vec!["clear"]);
+    }
+
+    #[test]
+    fn named_value_policy() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("set")
+                .parameter(Parameter::new("--name").kind(ParameterKind::Named))
+                .parameter(Parameter::new("show").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("set --name show").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--name"], "show");
+        assert!(!parser.parameters.contains_key("show"));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_named_value_policy(NamedValuePolicy::NextNode);
+        parser.parse(tokenize("set --name show").unwrap()).unwrap();
+        assert!(!parser.parameters.contains_key("--name"));
+        assert_eq!(parser.nodes.last().unwrap().node().name, "show");
+
+        // Other values are still taken as the value.
+        let mut parser = Parser::new(root);
+        parser.set_named_value_policy(NamedValuePolicy::NextNode);
+        parser.parse(tokenize("set --name other").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--name"], "other");
+    }
+
+    #[test]
+    fn parameters_in_order() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("copy")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("src").required(true))
+                .parameter(Parameter::new("--via").kind(ParameterKind::Named))
+                .parameter(Parameter::new("dst").required(true))
+                .parameter(Parameter::new("-v").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+        match *root.successors()[0] {
+            Node::Command(ref command) => {
+                let names = command
+                    .parameters_in_order()
+                    .iter()
+                    .map(|p| p.node.name.as_str())
+                    .collect::<Vec<_>>();
+                assert_eq!(names, vec!["--via", "-v", "src", "dst"]);
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("copy a --via x b -v").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+        assert_eq!(parser.parameters["src"], "a");
+        assert_eq!(parser.parameters["dst"], "b");
+        assert_eq!(parser.parameters["--via"], "x");
+    }
+
+    #[test]
+    fn help_header_and_footer() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .help_header("Show the state of the system.")
+                .help_footer("See also: set")
+                .parameter(
+                    Parameter::new("interface")
+                        .required(true)
+                        .help("The interface to show"),
+                ),
+        );
+        let root = tree.finalize();
+        match *root.successors()[0] {
+            Node::Command(ref command) => {
+                assert_eq!(
+                    command.help(),
+                    "Show the state of the system.\n\n\
+                     Usage: show <interface>\n\n\
+                     Parameters:\n  <interface>  The interface to show\n\n\
+                     See also: set\n"
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn greedy_parameter() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("echo")
+                .parameter(Parameter::new("-n").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("text").greedy(true)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("echo --weird   text ").unwrap()).unwrap();
+        assert_eq!(parser.parameters["text"], "--weird   text");
+        assert_eq!(parser.last_good_position(), 3);
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("echo -n -- -n text").unwrap()).unwrap();
+        assert_eq!(parser.parameters["-n"], "-n");
+        assert_eq!(parser.parameters["text"], "-n text");
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("echo")
+                .parameter(Parameter::new("--text").kind(ParameterKind::Named).greedy(true)),
+        );
+        let error = tree.try_finalize().unwrap_err();
+        assert_eq!(
+            error,
+            BuildError::GreedyNotSimple("echo".to_string(), "--text".to_string())
+        );
+        assert_eq!(error.to_string(), "The parameter '--text' of 'echo' is greedy but not simple.");
+    }
+
+    #[test]
+    fn unsatisfiable_command_warning() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("export")
+                .parameter(Parameter::new("--json").kind(ParameterKind::Flag).required(true))
+                .parameter(Parameter::new("--xml").kind(ParameterKind::Flag).required(true))
+                .parameter(Parameter::new("--csv").kind(ParameterKind::Flag))
+                .exclusive_group(&["--json", "--xml", "--csv"]),
+        );
+        tree.command(
+            Command::new("import")
+                .parameter(Parameter::new("--json").kind(ParameterKind::Flag).required(true))
+                .parameter(Parameter::new("--xml").kind(ParameterKind::Flag))
+                .exclusive_group(&["--json", "--xml"]),
+        );
+        match *tree.finalize() {
+            Node::Root(ref root) => {
+                assert_eq!(
+                    root.warnings,
+                    vec![
+                        BuildWarning::UnsatisfiableCommand(
+                            "export".to_string(),
+                            vec!["--json".to_string(), "--xml".to_string()],
+                        ),
+                    ]
+                );
+                assert_eq!(
+                    root.warnings[0].to_string(),
+                    "The command 'export' can not be given all of '--json', '--xml'."
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn complete_ranked_by_usage() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set"));
+        tree.command(Command::new("save").priority(PRIORITY_PARAMETER));
+        let mut parser = Parser::new(tree.finalize());
+
+        let symbols = |completions: Vec<Completion>| {
+            completions
+                .iter()
+                .map(|c| c.help_symbol.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(symbols(parser.complete_ranked(None)), vec!["set", "show", "save"]);
+
+        let mut stats = HashMap::new();
+        stats.insert("set".to_string(), 5);
+        stats.insert("save".to_string(), 10);
+        parser.set_usage_stats(stats);
+        assert_eq!(symbols(parser.complete_ranked(None)), vec!["set", "show", "save"]);
+    }
+
+    #[test]
+    fn complete_ranked_by_coverage() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("list"));
+        tree.command(Command::new("load"));
+        tree.command(Command::new("ls"));
+        tree.command(Command::new("lsof").alias("lo"));
+        let parser = Parser::new(tree.finalize());
+
+        let ranked = |input| {
+            parser
+                .complete_ranked(Some(tokenize(input).unwrap()[0]))
+                .iter()
+                .map(|c| c.help_symbol.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranked("l"), vec!["ls", "list", "load", "lsof"]);
+        assert_eq!(ranked("lo"), vec!["lsof", "load"]);
+    }
+
+    #[test]
+    fn experimental_parameters() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(
+                    Parameter::new("--fast")
+                        .kind(ParameterKind::Flag)
+                        .experimental(true),
+                )
+                .parameter(
+                    Parameter::new("--mode")
+                        .kind(ParameterKind::Named)
+                        .experimental(true),
+                ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.complete(None).is_empty());
+        assert!(parser.parse(tokenize("--fast").unwrap()).is_err());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.enable_experimental(true);
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert_eq!(parser.complete(None).len(), 2);
+        parser.parse(tokenize("--fast").unwrap()).unwrap();
+
+        let mut parser = Parser::new(root);
+        parser.enable_experimental(true);
+        parser.parse(tokenize("show --mode quick").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--mode"], "quick");
+    }
+
+    #[test]
+    fn usage_counts() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("set").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("help"));
+        let mut parser = Parser::new(tree.finalize());
+        parser.run("show").unwrap();
+        assert!(parser.usage_counts().is_empty());
+
+        parser.record_usage(true);
+        for line in &["show", "show", "set"] {
+            parser.reset();
+            parser.run(line).unwrap();
+        }
+        parser.reset();
+        parser.parse(tokenize("help").unwrap()).unwrap();
+        assert_eq!(parser.try_execute().unwrap(), ExecStatus::Continue);
+        assert_eq!(parser.usage_counts()["show"], 2);
+        assert_eq!(parser.usage_counts()["set"], 1);
+        assert!(!parser.usage_counts().contains_key("help"));
+
+        parser.clear_usage_counts();
+        assert!(parser.usage_counts().is_empty());
+    }
+
+    #[test]
+    fn help_symbol_override() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .help_symbol_override("show|display")
+                .parameter(
+                    Parameter::new("--level")
+                        .kind(ParameterKind::Named)
+                        .help_symbol_override("--level 1-5"),
+                )
+                .parameter(
+                    Parameter::new("host")
+                        .required(true)
+                        .help_symbol_override("HOST[:PORT]"),
+                ),
+        );
+        let root = tree.finalize();
+        match *root.successors()[0] {
+            Node::Command(ref command) => {
+                assert_eq!(command.node.help_symbol, "show|display");
+                assert_eq!(command.grammar(), "show|display --level 1-5 HOST[:PORT]");
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn require_confirmation_token() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("erase")
+                .handler(|_| ExecStatus::Exit)
+                .require_token("--yes"),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run("erase") {
+            Err(CommandError::Verify(VerifyError::ConfirmationRequired(ref token))) => {
+                assert_eq!(token, "--yes");
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(root);
+        match parser.run("erase --yes") {
+            Ok(ExecStatus::Exit) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parameters_by_kind() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ping")
+                .parameter(Parameter::new("host"))
+                .parameter(Parameter::new("--count").kind(ParameterKind::Named))
+                .parameter(Parameter::new("-q").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+        let names = |parameters: Vec<&ParameterNode>| {
+            parameters
+                .iter()
+                .map(|p| p.node.name.clone())
+                .collect::<Vec<_>>()
+        };
+        match *root.successors()[0] {
+            Node::Command(ref command) => {
+                assert_eq!(names(command.flag_parameters()), vec!["-q"]);
+                assert_eq!(names(command.named_parameters()), vec!["--count"]);
+                assert_eq!(names(command.simple_parameters()), vec!["host"]);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_recovery() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ping")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--count").kind(ParameterKind::Named))
+                .parameter(Parameter::new("-q").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert!(parser.parse(tokenize("ping bad --count 3 worse -q").unwrap()).is_err());
+
+        let mut parser = Parser::new(root);
+        parser.set_recovery(true);
+        parser.parse(tokenize("ping bad --count 3 worse -q").unwrap()).unwrap();
+        let skipped = parser
+            .recovered_errors()
+            .iter()
+            .map(|e| match *e {
+                ParseError::NoMatches(token, _) => token.text,
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(skipped, vec!["bad", "worse"]);
+        assert_eq!(parser.parameters["--count"], "3");
+        assert_eq!(parser.parameters["-q"], "-q");
+        assert!(parser.verify().is_ok());
+    }
+
+    #[test]
+    fn search_terms() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("remove")
+                .auto_short_alias(true)
+                .help("Remove the files, with the given names, from the index."),
+        );
+        let root = tree.finalize();
+        match *root.successors()[0] {
+            Node::Command(ref command) => {
+                assert_eq!(
+                    command.search_terms(),
+                    vec!["remove", "r", "files", "given", "names", "index"]
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn choice_fills_shared_slot() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("deploy")
+                .handler(|_| ExecStatus::Continue)
+                .choice(
+                    "target",
+                    vec![
+                        Parameter::new("--host").kind(ParameterKind::Named),
+                        Parameter::new("--group").kind(ParameterKind::Named),
+                        Parameter::new("--region").kind(ParameterKind::Named),
+                    ],
+                ),
+        );
+        let root = tree.try_finalize().unwrap();
+
+        for &(line, member, value) in &[
+            ("deploy --host web1", "--host", "web1"),
+            ("deploy --group web", "--group", "web"),
+            ("deploy --region eu", "--region", "eu"),
+        ] {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(line).unwrap()).unwrap();
+            assert!(parser.verify().is_ok());
+            assert_eq!(parser.parameters["target"], member);
+            assert_eq!(parser.parameters[member], value);
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("deploy --host web1 --group web").unwrap()).unwrap();
+        match parser.verify() {
+            Err(VerifyError::ConflictingParameters(ref first, ref second)) => {
+                assert_eq!((first.as_str(), second.as_str()), ("--host", "--group"));
+            }
+            _ => panic!("expected the members of the choice to conflict"),
+        }
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("deploy")
+                .choice("target", vec![Parameter::new("--host").kind(ParameterKind::Named)])
+                .parameter(Parameter::new("target")),
+        );
+        let error = tree.try_finalize().unwrap_err();
+        assert_eq!(error, BuildError::SlotConflict("deploy".to_string(), "target".to_string()));
+        assert_eq!(
+            error.to_string(),
+            "The command 'deploy' has a choice and a parameter 'target'."
+        );
+    }
+
+    #[test]
+    fn render_error_with_source() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("-v").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+
+        let input = "show -v bogus";
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run(input) {
+            Err(err) => {
+                assert_eq!(
+                    err.render_with_source(input),
+                    "No match for 'bogus' (0 options).\nshow -v bogus\n        ^^^^^"
+                );
+            }
+            _ => panic!(),
+        }
+
+        let input = "show \"unclosed";
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run(input) {
+            Err(err) => {
+                let rendered = err.render_with_source(input);
+                assert_eq!(rendered.lines().last(), Some("     ^"));
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(root);
+        match parser.run("") {
+            Err(err) => assert_eq!(err.render_with_source(""), err.to_string()),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn lazy_command() {
+        thread_local! {
+            static BUILT: Cell<usize> = const { Cell::new(0) };
+        }
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Continue));
+        tree.lazy_command(
+            "erase",
+            Box::new(|| {
+                BUILT.with(|b| b.set(b.get() + 1));
+                Command::new("erase")
+                    .handler(|_| ExecStatus::Continue)
+                    .parameter(Parameter::new("-f").kind(ParameterKind::Flag))
+            }),
+        );
+        let root = tree.finalize();
+        assert_eq!(BUILT.with(|b| b.get()), 0);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert_eq!(BUILT.with(|b| b.get()), 0);
+
+        for _ in 0..2 {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize("erase -f").unwrap()).unwrap();
+            assert!(parser.verify().is_ok());
+            assert_eq!(parser.parameters["-f"], "-f");
+        }
+        assert_eq!(BUILT.with(|b| b.get()), 1);
+    }
+
+    #[test]
+    fn to_command_line_round_trip() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("commit")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--message").kind(ParameterKind::Named))
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("path")),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("comm src --a --m \"fix it; again\"").unwrap()).unwrap();
+        let line = parser.to_command_line();
+        assert_eq!(line, "commit src --all --message \"fix it; again\"");
+
+        let mut reparsed = Parser::new(root);
+        reparsed.parse(tokenize(&line).unwrap()).unwrap();
+        assert_eq!(reparsed.parameters["--message"], "fix it; again");
+        assert_eq!(reparsed.parameters["path"], "src");
+        assert_eq!(reparsed.to_command_line(), line);
+    }
+
+    #[test]
+    fn complete_commands_and_flags() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .help("Show things.")
+                .parameter(Parameter::new("--help").kind(ParameterKind::Flag).help("Help."))
+                .parameter(Parameter::new("--heading").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag)),
+        );
+        tree.command(Command::new("set"));
+        tree.command(Command::new("secret").hidden(true));
+        let root = tree.finalize();
+
+        let options = |completions: Vec<Completion>| {
+            completions
+                .iter()
+                .flat_map(|c| c.options.iter().map(|o| o.option_string.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // Hidden commands are not offered, even when they match.
+        let parser = Parser::new(Rc::clone(&root));
+        assert_eq!(options(parser.complete(None)), vec!["set", "show"]);
+        let tokens = tokenize("se").unwrap();
+        assert_eq!(options(parser.complete(Some(tokens[0]))), vec!["set"]);
+
+        // After a command, its parameters are offered.
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert_eq!(options(parser.complete(None)), vec!["--heading", "--help", "--verbose"]);
+
+        let tokens = tokenize("--he").unwrap();
+        let completions = parser.complete(Some(tokens[0]));
+        assert_eq!(completions[1].help_text, "Help.");
+        assert_eq!(options(completions), vec!["--heading", "--help"]);
+    }
+
+    #[test]
+    fn case_insensitive_matching() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("set").handler(|_| ExecStatus::Continue));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert!(parser.parse(tokenize("SHOW").unwrap()).is_err());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_case_insensitive(true);
+        parser.parse(tokenize("SHOW").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+        assert_eq!(parser.nodes[0].node().name, "show");
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_case_insensitive(true);
+        match parser.parse(tokenize("S").unwrap()) {
+            Err(ParseError::AmbiguousMatch(_, ref matches)) => assert_eq!(matches.len(), 2),
+            _ => panic!(),
+        }
+
+        let tokens = tokenize("SH").unwrap();
+        let completions = parser.complete(Some(tokens[0]));
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].options[0].option_string, "show");
+    }
+
+    #[test]
+    fn prefix_matching() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("showtech").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("set").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("save").handler(|_| ExecStatus::Continue));
+        tree.command(Command::new("reload").handler(|_| ExecStatus::Continue));
+        tree.command(
+            Command::new("restart")
+                .handler(|_| ExecStatus::Continue)
+                .priority(PRIORITY_DEFAULT + 1),
+        );
+        let root = tree.finalize();
+        let matched = |line: &str, prefix_matching: bool| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.set_prefix_matching(prefix_matching);
+            match parser.parse(tokenize(line).unwrap()) {
+                Ok(()) => Ok(parser.nodes[0].node().name.clone()),
+                Err(ParseError::AmbiguousMatch(_, matches)) => Err(matches.len()),
+                Err(_) => Err(0),
+            }
+        };
+
+        // A unique prefix.
+        assert_eq!(matched("sa", true), Ok("save".to_string()));
+        // An ambiguous prefix.
+        assert_eq!(matched("s", true), Err(4));
+        // A prefix which is also a command.
+        assert_eq!(matched("show", true), Ok("show".to_string()));
+        assert_eq!(matched("showt", true), Ok("showtech".to_string()));
+        // A higher priority breaks the tie.
+        assert_eq!(matched("re", true), Ok("restart".to_string()));
+
+        // Without prefix matching, names must be given in full.
+        assert_eq!(matched("sa", false), Err(0));
+        assert_eq!(matched("save", false), Ok("save".to_string()));
+        assert_eq!(matched("show", false), Ok("show".to_string()));
+    }
+
+    #[test]
+    fn default_values() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ping")
+                .handler(|p| {
+                    if p.parameters["--count"] == "5" && p.parameters["host"] == "localhost" {
+                        ExecStatus::Exit
+                    } else {
+                        ExecStatus::Continue
+                    }
+                })
+                .parameter(Parameter::new("host").default_value("localhost"))
+                .parameter(Parameter::new("--count").kind(ParameterKind::Named).default_value("5")),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.run("ping").unwrap(), ExecStatus::Exit);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.run("ping --count 3").unwrap(), ExecStatus::Continue);
+        assert_eq!(parser.parameters["--count"], "3");
+        assert_eq!(parser.parameters["host"], "localhost");
+
+        // An empty value is still a value.
+        let mut parser = Parser::new(root);
+        parser.run("ping ''").unwrap();
+        assert_eq!(parser.parameters["host"], "");
+    }
+
+    #[test]
+    fn typed_values() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("resize")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("count").value_type(ValueType::Integer))
+                .parameter(
+                    Parameter::new("--scale")
+                        .kind(ParameterKind::Named)
+                        .value_type(ValueType::Float),
+                ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.run("resize 12 --scale 1.5").unwrap();
+        assert_eq!(parser.typed_value("count"), Some(TypedValue::Integer(12)));
+        assert_eq!(parser.typed_value("--scale"), Some(TypedValue::Float(1.5)));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run("resize twelve") {
+            Err(CommandError::Verify(ref err @ VerifyError::InvalidValue(..))) => {
+                assert_eq!(err.to_string(), "The value 'twelve' of count must be an integer.");
+            }
+            _ => panic!(),
+        }
+        assert_eq!(parser.typed_value("count"), None);
+
+        let mut parser = Parser::new(root);
+        match parser.run("resize 1 --scale big") {
+            Err(CommandError::Verify(ref err)) => {
+                assert_eq!(err.to_string(), "The value 'big' of --scale must be a number.");
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_error_display() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let err = parser.parse(tokenize("bogus").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "No match for 'bogus' (2 options).");
+
+        let mut parser = Parser::new(root);
+        let err = parser.parse(tokenize("s").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "Ambiguous match for 's': set, show.");
+        let err: Box<dyn Error> = Box::new(err);
+        assert_eq!(err.to_string(), "Ambiguous match for 's': set, show.");
+    }
+
+    #[test]
+    fn parameter_values() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("build")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("target"))
+                .parameter(
+                    Parameter::new("--feature")
+                        .kind(ParameterKind::Named)
+                        .alias("-F")
+                        .repeatable(true),
+                )
+                .parameter(Parameter::new("--profile").kind(ParameterKind::Named)),
+        );
+        let mut parser = Parser::new(tree.finalize());
+        parser.run("build app --feat x -F y").unwrap();
+
+        let values = parser.parameters();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["target"], ParameterValue::Single("app".to_string()));
+        assert_eq!(
+            values["--feature"],
+            ParameterValue::Multiple(vec!["x".to_string(), "y".to_string()])
+        );
+        assert!(!values.contains_key("--profile"));
+    }
+
+    #[test]
+    fn closure_handler() {
+        let runs = Rc::new(Cell::new(0));
+        let verbose = Rc::new(Cell::new(false));
+        let mut tree = CommandTree::new();
+        {
+            let runs = Rc::clone(&runs);
+            let verbose = Rc::clone(&verbose);
+            tree.command(
+                Command::new("show")
+                    .handler(move |parser| {
+                        runs.set(runs.get() + 1);
+                        verbose.set(parser.parameters().contains_key("--verbose"));
+                        ExecStatus::Continue
+                    })
+                    .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag)),
+            );
+        }
+        let mut parser = Parser::new(tree.finalize());
+        assert_eq!(parser.run("show --verbose").unwrap(), ExecStatus::Continue);
+        assert_eq!(runs.get(), 1);
+        assert!(verbose.get());
+    }
+
+    #[test]
+    fn duplicate_commands() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("set"));
+        tree.command(Command::new("set").hidden(true));
+        assert!(tree.try_finalize().is_ok());
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("show"));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::DuplicateCommand("show".to_string()))
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").priority(PRIORITY_MINIMUM));
+        tree.command(Command::new("show").priority(PRIORITY_DEFAULT));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::DuplicateCommand("show".to_string()))
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.path_alias("show", &["set"]);
+        assert!(tree.try_finalize().is_err());
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .subcommand(Command::new("interface"))
+                .subcommand(Command::new("route"))
+                .subcommand(Command::new("route").hidden(true)),
+        );
+        tree.command(Command::new("set").subcommand(Command::new("interface")));
+        assert!(tree.try_finalize().is_ok());
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show").subcommand(
+                Command::new("interface")
+                    .subcommand(Command::new("brief"))
+                    .subcommand(Command::new("detail").alias("brief")),
+            ),
+        );
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::DuplicateCommand("show interface brief".to_string()))
+        );
+    }
+
+    #[test]
+    fn nested_subcommands() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show").subcommand(
+                Command::new("interface")
+                    .handler(|_| ExecStatus::Continue)
+                    .subcommand(Command::new("brief").handler(|_| ExecStatus::Exit)),
+            ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.run("show interface").unwrap(), ExecStatus::Continue);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert_eq!(parser.run("sh int br").unwrap(), ExecStatus::Exit);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show interface ").unwrap()).unwrap();
+        let completions = parser.complete(None);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].options[0].option_string, "brief");
+
+        let mut parser = Parser::new(root);
+        match parser.parse(tokenize("show interface bogus").unwrap()) {
+            Err(ParseError::NoMatches(token, _)) => {
+                assert_eq!(token.text, "bogus");
+                assert_eq!(parser.nodes.len(), 2);
+            }
+            _ => panic!("expected no matches"),
+        }
+    }
+
+    #[test]
+    fn debug_formatting() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag))
+                .subcommand(Command::new("interface")),
+        );
+        let tree_debug = format!("{:?}", tree);
+        assert!(tree_debug.contains("\"show\""));
+        assert!(tree_debug.contains("\"--verbose\""));
+
+        let root = tree.finalize();
+        let root_debug = format!("{:?}", root);
+        assert!(root_debug.contains("commands: [\"show\"]"));
+
+        let show = &root.node().successors[0];
+        let show_debug = format!("{:?}", show);
+        assert!(show_debug.contains("name: \"show\""));
+        assert!(show_debug.contains("successors: [\"--verbose\", \"interface\"]"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_commands() {
+        let json = r#"[
+            {"name": "show", "priority": 10, "help_text": "Show things.", "parameters": [
+                {"name": "--item", "kind": "Named", "required": true, "aliases": ["-i"]}
+            ]},
+            {"name": "debug", "hidden": true}
+        ]"#;
+        let commands: Vec<Command> = serde_json::from_str(json).unwrap();
+        let serialized = serde_json::to_string(&commands).unwrap();
+        let tree = CommandTree::from_commands(commands);
+        let root = tree.finalize();
+        assert_eq!(root.successors()[0].node().priority, 10);
+        assert!(root.successors()[1].node().hidden);
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show -i disk").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--item"], "disk");
+
+        let commands: Vec<Command> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(serde_json::to_string(&commands).unwrap(), serialized);
+        assert!(serialized.contains(r#""hidden":true"#));
+        assert!(serialized.contains(r#""required":true"#));
+        assert!(serialized.contains(r#""aliases":["-i"]"#));
+
+        let error = serde_json::from_str::<Vec<Command>>(r#"[{"hidden": true}]"#).unwrap_err();
+        assert!(error.to_string().contains("missing field `name`"));
+        let json = r#"[{"name": "show", "parameters": [{"kind": "Flag"}]}]"#;
+        let error = serde_json::from_str::<Vec<Command>>(json).unwrap_err();
+        assert!(error.to_string().contains("missing field `name`"));
+    }
+
+    #[test]
+    fn help_listing_and_command_help() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .help("Show an item.")
+                .parameter(Parameter::new("name").required(true))
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--debug").kind(ParameterKind::Flag).hidden(true))
+                .subcommand(Command::new("interface").help("Show an interface.")),
+        );
+        tree.command(Command::new("reload").help("Reload the configuration."));
+        tree.command(Command::new("secret").hidden(true));
+        let root = tree.finalize();
+        let root = match *root {
+            Node::Root(ref root) => root,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            root.help_listing(),
+            "Commands:\n  show    Show an item.\n  reload  Reload the configuration.\n"
+        );
+
+        let help = root.command_help(&["show"]).unwrap();
+        assert!(help.starts_with("Usage: show <name> [--all] interface\n"));
+        assert!(!help.contains("--debug"));
+        assert_eq!(
+            root.command_help(&["show", "interface"]).unwrap(),
+            "Usage: show interface\n"
+        );
+        assert_eq!(root.command_help(&["sh", "interface"]), None);
+        assert!(root.command_help(&["show", "bogus"]).is_none());
+
+        assert_eq!(
+            root.help_listing_with_hidden(),
+            "Commands:\n  show    Show an item.\n  reload  Reload the configuration.\n  \
+             secret  Command (hidden)\n"
+        );
+        let help = root.command_help_with_hidden(&["show"]).unwrap();
+        assert!(help.starts_with("Usage: show <name> [--all] [--debug] interface\n"));
+        assert!(help.contains("  [--debug]  Flag (hidden)\n"));
+        assert_eq!(help.matches("(hidden)").count(), 1);
+    }
+
+    #[test]
+    fn parameter_aliases() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ls")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag).alias("-a"))
+                .parameter(Parameter::new("--sort").kind(ParameterKind::Named).alias("-s")),
+        );
+        let mut parser = Parser::new(tree.finalize());
+        parser.parse(tokenize("ls -a -s size").unwrap()).unwrap();
+        assert!(parser.parameters.contains_key("--all"));
+        assert_eq!(parser.parameters["--sort"], "size");
+        assert!(!parser.parameters.contains_key("-a"));
+        assert!(!parser.parameters.contains_key("-s"));
+    }
+
+    #[test]
+    fn wrapped_commands() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show").subcommand(
+                Command::new("interface")
+                    .handler(|_| ExecStatus::Continue)
+                    .parameter(Parameter::new("name").required(true)),
+            ),
+        );
+        tree.command(
+            Command::new("int")
+                .handler(|_| ExecStatus::Exit)
+                .wraps("show interface".to_string()),
+        );
+        let root = tree.try_finalize().unwrap();
+        match *root.successors()[1] {
+            Node::Command(ref command) => {
+                assert_eq!(command.wrapped_root.as_ref().unwrap().node().name, "interface");
+            }
+            _ => panic!("expected a command"),
+        }
+
+        let mut parser = Parser::new(root);
+        assert_eq!(parser.run("int eth0").unwrap(), ExecStatus::Exit);
+        assert_eq!(parser.parameters["name"], "eth0");
+
+        let mut parser = Parser::new(Rc::clone(&parser.root));
+        parser.parse(tokenize("int").unwrap()).unwrap();
+        match parser.verify() {
+            Err(VerifyError::MissingParameter(ref names)) if names == &["name"] => {}
+            _ => panic!("expected a missing parameter"),
+        }
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("int").wraps("show bogus".to_string()));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::UnresolvedWrap("int".to_string(), "show bogus".to_string()))
+        );
+        assert!(tree.finalize().successors()[1].successors().is_empty());
+    }
+
+    #[test]
+    fn command_aliases() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").alias("display").handler(|_| ExecStatus::Exit));
+        tree.command(Command::new("set").handler(|_| ExecStatus::Continue));
+        let root = tree.try_finalize().unwrap();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_prefix_matching(false);
+        assert_eq!(parser.run("display").unwrap(), ExecStatus::Exit);
+
+        let parser = Parser::new(Rc::clone(&root));
+        let options = |parser: &Parser| {
+            parser
+                .complete(None)
+                .iter()
+                .flat_map(|c| c.options.iter().map(|o| o.option_string.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(options(&parser), vec!["set", "show"]);
+        let mut parser = Parser::new(root);
+        parser.set_complete_aliases(true);
+        assert_eq!(options(&parser), vec!["set", "show", "display"]);
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show").alias("display"));
+        tree.command(Command::new("list").alias("display"));
+        assert_eq!(
+            tree.try_finalize().err(),
+            Some(BuildError::DuplicateCommand("display".to_string()))
+        );
+    }
+
+    #[test]
+    fn occurrence_limits() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("tag").handler(|_| ExecStatus::Continue).parameter(
+                Parameter::new("--name")
+                    .kind(ParameterKind::Named)
+                    .repeatable(true)
+                    .min_occurs(2)
+                    .max_occurs(3),
+            ),
+        );
+        let root = tree.finalize();
+        let verify = |input: &str| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(input).unwrap()).unwrap();
+            parser.verify().map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            verify("tag --name a"),
+            Err("--name must be given at least 2 times.".to_string())
+        );
+        assert!(verify("tag --name a --name b").is_ok());
+        assert!(verify("tag --name a --name b --name c").is_ok());
+        assert_eq!(
+            verify("tag --name a --name b --name c --name d"),
+            Err("--name may be given at most 3 times.".to_string())
+        );
+        // The fewest times are only checked when the parameter is given.
+        assert!(verify("tag").is_ok());
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("tag")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--force").kind(ParameterKind::Flag))
+                .parameter(
+                    Parameter::new("--name")
+                        .kind(ParameterKind::Named)
+                        .repeatable(true)
+                        .required(true)
+                        .min_occurs(2),
+                ),
+        );
+        let root = tree.finalize();
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("tag").unwrap()).unwrap();
+        match parser.verify() {
+            Err(VerifyError::MissingParameter(ref names)) => assert_eq!(names, &["--name"]),
+            _ => panic!("expected --name to be missing"),
+        }
+
+        // A parameter which isn't repeatable can't be given twice.
+        let mut parser = Parser::new(root);
+        match parser.parse(tokenize("tag --force --name a --force").unwrap()) {
+            Err(ParseError::NoMatches(token, _)) => assert_eq!(token.text, "--force"),
+            _ => panic!("expected --force not to match again"),
+        }
+    }
+
+    #[test]
+    fn missing_parameters() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("copy")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("source").required(true))
+                .parameter(Parameter::new("destination").required(true))
+                .parameter(Parameter::new("--force").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("copy").unwrap()).unwrap();
+        match parser.verify() {
+            Err(error @ VerifyError::MissingParameter(_)) => {
+                assert_eq!(error.to_string(), "Missing required parameters: source, destination.");
+            }
+            _ => panic!("expected missing parameters"),
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("copy a b").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+    }
+
+    #[test]
+    fn candidates_ordered_by_priority() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("status").priority(PRIORITY_MINIMUM));
+        tree.command(Command::new("show"));
+        tree.command(Command::new("save").priority(PRIORITY_DEFAULT + 10));
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let parser = Parser::new(Rc::clone(&root));
+        let names = parser
+            .complete(None)
+            .iter()
+            .map(|c| c.help_symbol.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["save", "set", "show", "status"]);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_prefix_matching(false);
+        match parser.parse(tokenize("bogus").unwrap()) {
+            Err(ParseError::NoMatches(_, ref acceptable)) => {
+                let names = acceptable.iter().map(|n| n.node().name.as_str()).collect::<Vec<_>>();
+                assert_eq!(names, vec!["save", "set", "show", "status"]);
+            }
+            _ => panic!("expected no matches"),
+        }
+
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("stop"));
+        tree.command(Command::new("start"));
+        let mut parser = Parser::new(tree.finalize());
+        match parser.parse(tokenize("st").unwrap()) {
+            Err(ParseError::AmbiguousMatch(_, ref matches)) => {
+                let names = matches.iter().map(|n| n.node().name.as_str()).collect::<Vec<_>>();
+                assert_eq!(names, vec!["start", "stop"]);
+            }
+            _ => panic!("expected an ambiguous match"),
+        }
+    }
+
+    #[test]
+    fn reset_between_lines() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("item").required(true)),
+        );
+        tree.command(Command::new("SET").handler(|_| ExecStatus::Exit));
+        let mut parser = Parser::new(tree.finalize());
+        parser.set_case_insensitive(true);
+
+        parser.parse(tokenize("show disk").unwrap()).unwrap();
+        assert_eq!(parser.parameters()["item"], ParameterValue::Single("disk".to_string()));
+
+        parser.reset();
+        assert!(parser.parameters().is_empty());
+        assert!(parser.nodes.is_empty());
+        match parser.verify() {
+            Err(VerifyError::NoCommandAccepted) => {}
+            _ => panic!("expected no command to have been accepted"),
+        }
+
+        parser.parse(tokenize("set").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+        assert_eq!(parser.execute(), ExecStatus::Exit);
+    }
+
+    #[test]
+    fn matched_command_path() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("item").required(true))
+                .subcommand(
+                    Command::new("interface")
+                        .subcommand(Command::new("brief").handler(|_| ExecStatus::Continue)),
+                ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert!(parser.matched_command().is_none());
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(parser.verify().is_err());
+        assert_eq!(parser.matched_command().unwrap().node.name, "show");
+        assert_eq!(parser.command_path(), vec!["show"]);
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show interface brief").unwrap()).unwrap();
+        assert_eq!(parser.matched_command().unwrap().node.name, "brief");
+        assert_eq!(parser.command_path(), vec!["show", "interface", "brief"]);
+    }
+
+    #[test]
+    fn named_parameter_with_glued_value() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("draw")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--color").kind(ParameterKind::Named).alias("-c"))
+                .parameter(Parameter::new("--expr").kind(ParameterKind::Named)),
+        );
+        let root = tree.finalize();
+        let parse = |input| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(input).unwrap()).map(|_| parser.parameters)
+        };
+
+        assert_eq!(parse("draw --color=red").unwrap()["--color"], "red");
+        assert_eq!(parse("draw -c=blue").unwrap()["--color"], "blue");
+        assert_eq!(parse("draw --col=red").unwrap()["--color"], "red");
+        assert_eq!(parse("draw --expr=a=b").unwrap()["--expr"], "a=b");
+        match parse("draw --color=") {
+            Err(ParseError::NoMatches(token, acceptable)) => {
+                assert_eq!(token.text, "--color=");
+                assert_eq!(acceptable.len(), 1);
+                assert_eq!(acceptable[0].node().name, "--color");
+            }
+            _ => panic!("expected a missing value"),
+        }
     }
-}
 
-/// Errors that calling `verify` on the `Parser` can raise.
-#[derive(Clone, Debug)]
-pub enum VerifyError {
-    /// No command has been accepted by the parser.
-    NoCommandAccepted,
-    /// A required parameter is missing.
-    MissingParameter(String),
-}
+    #[test]
+    fn negatable_flags() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ls")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--color").kind(ParameterKind::Flag).negatable(true)),
+        );
+        let root = tree.finalize();
+        let run = |input| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(input).unwrap()).unwrap();
+            parser.verify().map(|_| parser.parameters).map_err(|e| e.to_string())
+        };
 
-impl Error for VerifyError {
-    fn description(&self) -> &str {
-        match *self {
-            VerifyError::NoCommandAccepted => "No command has been accepted by the parser.",
-            VerifyError::MissingParameter(_) => "A required parameter is missing.",
+        assert_eq!(run("ls --color").unwrap()["--color"], "true");
+        assert_eq!(run("ls --no-color").unwrap()["--color"], "false");
+        assert!(!run("ls").unwrap().contains_key("--color"));
+        assert_eq!(
+            run("ls --color --no-color").unwrap_err(),
+            "--color and --no-color may not both be given."
+        );
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("ls").unwrap()).unwrap();
+        let options = parser
+            .complete(None)
+            .iter()
+            .flat_map(|c| c.options.iter().map(|o| o.option_string.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(options, vec!["--color", "--no-color"]);
+    }
+
+    #[test]
+    fn short_flags() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("tar")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--create").kind(ParameterKind::Flag).short('c'))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag).short('v'))
+                .parameter(Parameter::new("--gzip").kind(ParameterKind::Flag).short('z'))
+                .parameter(Parameter::new("--file").kind(ParameterKind::Named).short('f')),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("tar -v -f out.tar").unwrap()).unwrap();
+        assert!(parser.parameters.contains_key("--verbose"));
+        assert_eq!(parser.parameters["--file"], "out.tar");
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_short_clusters(true);
+        parser.parse(tokenize("tar -czv").unwrap()).unwrap();
+        assert!(parser.parameters.contains_key("--create"));
+        assert!(parser.parameters.contains_key("--gzip"));
+        assert!(parser.parameters.contains_key("--verbose"));
+
+        let mut parser = Parser::new(root);
+        parser.set_short_clusters(true);
+        match parser.parse(tokenize("tar -cxv").unwrap()) {
+            Err(ParseError::NoMatches(token, _)) => {
+                assert_eq!(token.text, "x");
+                assert_eq!(token.span(), 6..7);
+            }
+            _ => panic!("expected no matches"),
         }
     }
-}
 
-impl fmt::Display for VerifyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.description().fmt(f)
+    #[test]
+    fn values_after_end_of_options() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("grep")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--count").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("pattern"))
+                .parameter(Parameter::new("file")),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("grep -- --weird --count").unwrap()).unwrap();
+        assert_eq!(parser.parameters["pattern"], "--weird");
+        assert_eq!(parser.parameters["file"], "--count");
+        assert!(!parser.parameters.contains_key("--count"));
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("grep --").unwrap()).unwrap();
+        let completions = parser.complete(None);
+        let symbols = completions.iter().map(|c| c.help_symbol.as_str()).collect::<Vec<_>>();
+        assert!(!symbols.contains(&"--count"));
+        assert!(symbols.iter().all(|s| s.starts_with('[') || s.starts_with('<')));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use tokenizer::tokenize;
+    #[test]
+    fn exclusive_groups_verified() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("export")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--json").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--xml").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--csv").kind(ParameterKind::Flag))
+                .parameter(
+                    Parameter::new("--format").kind(ParameterKind::Named).default_value("text"),
+                )
+                .exclusive_group(&["--json", "--xml", "--csv"])
+                .exclusive_group(&["--format", "--json"])
+                .exclusive_group(&["--csv"])
+                .exclusive_group(&[]),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("export --csv").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("export --xml --json").unwrap()).unwrap();
+        match parser.verify() {
+            Err(VerifyError::ConflictingParameters(first, second)) => {
+                assert_eq!(first, "--json");
+                assert_eq!(second, "--xml");
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("export --json --csv").unwrap()).unwrap();
+        assert_eq!(
+            parser.verify().unwrap_err().to_string(),
+            "--json and --csv may not both be given."
+        );
+
+        // A default value doesn't count as being given.
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("export --json").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--format"], "text");
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("export --json --format xml").unwrap()).unwrap();
+        assert!(parser.verify().is_err());
+    }
 
     #[test]
-    #[should_panic]
-    fn verify_signals_no_command() {
-        let root = CommandTree::new().finalize();
-        let parser = Parser::new(root);
+    fn parameter_dependencies() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--host").kind(ParameterKind::Named))
+                .parameter(Parameter::new("--port").kind(ParameterKind::Named).requires("--host"))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag))
+                .parameter(
+                    Parameter::new("--quiet").kind(ParameterKind::Flag).conflicts_with("--verbose"),
+                ),
+        );
+        let root = tree.try_finalize().unwrap();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("connect --port 22 --host example.com").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("connect --port 22").unwrap()).unwrap();
         match parser.verify() {
-            Err(VerifyError::NoCommandAccepted) => panic!(),
-            _ => {}
+            Err(VerifyError::MissingDependency(name, required)) => {
+                assert_eq!(name, "--port");
+                assert_eq!(required, "--host");
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("connect --verbose --quiet").unwrap()).unwrap();
+        assert_eq!(
+            parser.verify().unwrap_err().to_string(),
+            "--quiet and --verbose may not both be given."
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .parameter(Parameter::new("--port").kind(ParameterKind::Named).requires("--hots")),
+        );
+        assert_eq!(
+            tree.try_finalize().unwrap_err(),
+            BuildError::UnknownParameter("--port".to_string(), "--hots".to_string())
+        );
+    }
+
+    #[test]
+    fn default_name_completion() {
+        let command =
+            CommandNode::new("show", Some("Show things"), false, 0, vec![], None, vec![], &[]);
+        let completion = command.complete(Some(tokenize("sh").unwrap()[0]));
+        assert_eq!(completion.help_text, "Show things");
+        assert_eq!(completion.options[0].option_string, "show");
+        let completion = command.complete(Some(tokenize("x").unwrap()[0]));
+        assert!(completion.options.iter().all(|o| !o.complete));
+
+        let flag = ParameterNode::new(
+            "--force",
+            None,
+            false,
+            0,
+            vec![],
+            false,
+            None,
+            ParameterKind::Flag,
+            false,
+        );
+        let completion = flag.complete(Some(tokenize("--f").unwrap()[0]));
+        assert_eq!(completion.options.len(), 1);
+        assert_eq!(completion.options[0].option_string, "--force");
+        assert!(completion.options[0].complete);
+
+        // A parameter name uses the default implementation.
+        let value = Rc::new(Node::Parameter(ParameterNode::new(
+            "--port",
+            None,
+            false,
+            0,
+            vec![],
+            false,
+            None,
+            ParameterKind::Named,
+            false,
+        )));
+        let name = ParameterNameNode::new("--port", false, 0, vec![], false, None, value);
+        let completion = name.complete(Some(tokenize("--p").unwrap()[0]));
+        assert_eq!(completion.options.len(), 1);
+        assert_eq!(completion.options[0].option_string, "--port");
+
+        // A node which doesn't give its `TreeNode` has no options.
+        struct Custom;
+        impl NodeOps for Custom {
+            fn accept<'text>(&self, _: &mut Parser<'text>, _: Token, _: &Rc<Node>) {}
+            fn acceptable(&self, _: &Parser, _: &Rc<Node>) -> bool {
+                true
+            }
+            fn matches(&self, _: &Parser, _: Token) -> bool {
+                true
+            }
         }
+        assert!(Custom.complete(None).options.iter().all(|o| !o.complete));
     }
 
     #[test]
-    #[should_panic]
-    fn parse_signals_no_matches() {
+    fn dynamic_value_completion() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
         let mut tree = CommandTree::new();
-        tree.command(Command::new("show"));
-        let mut parser = Parser::new(tree.finalize());
-        if let Ok(tokens) = tokenize("h") {
-            match parser.parse(tokens) {
-                Err(ParseError::NoMatches(_, _)) => panic!(),
-                _ => {}
+        tree.command(
+            Command::new("deploy")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--region").kind(ParameterKind::Named).complete_with(
+                    move |_| {
+                        counter.set(counter.get() + 1);
+                        vec!["alpha".to_string(), "beta".to_string()]
+                    },
+                )),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("deploy").unwrap()).unwrap();
+        parser.complete(None);
+        assert_eq!(calls.get(), 0);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("deploy --region").unwrap()).unwrap();
+        let completions = parser.complete(None);
+        let options = completions[0].options.iter().map(|o| &o.option_string).collect::<Vec<_>>();
+        assert!(options.contains(&&"alpha".to_string()));
+        assert!(options.contains(&&"beta".to_string()));
+
+        let completions = parser.complete(Some(tokenize("al").unwrap()[0]));
+        let options = completions[0]
+            .options
+            .iter()
+            .filter(|o| o.complete)
+            .map(|o| o.option_string.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(options, vec!["alpha"]);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn command_tree_from_json() {
+        let json = r#"{"commands": [
+            {"name": "show", "help": "Show an item.", "priority": 10, "parameters": [
+                {"name": "--item", "kind": "named", "required": true, "aliases": ["-i"]},
+                {"name": "--all", "kind": "flag", "help": "Show everything."},
+                {"name": "target"}
+            ]},
+            {"name": "debug", "hidden": true}
+        ]}"#;
+        let tree = CommandTree::from_json(json).unwrap();
+
+        let mut expected = CommandTree::new();
+        expected.command(
+            Command::new("show")
+                .help("Show an item.")
+                .priority(10)
+                .parameter(
+                    Parameter::new("--item").kind(ParameterKind::Named).required(true).alias("-i"),
+                )
+                .parameter(
+                    Parameter::new("--all").kind(ParameterKind::Flag).help("Show everything."),
+                )
+                .parameter(Parameter::new("target")),
+        );
+        expected.command(Command::new("debug").hidden(true));
+        assert!(tree.diff(&expected).is_empty());
+
+        let root = tree.try_finalize().unwrap();
+        assert_eq!(root.successors()[0].node().priority, 10);
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show -i disk --all").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--item"], "disk");
+        assert!(parser.parameters.contains_key("--all"));
+
+        let json = r#"{"commands": [{"name": "show", "parameters": [
+            {"name": "--all", "kind": "switch"}
+        ]}]}"#;
+        let error = CommandTree::from_json(json).unwrap_err();
+        assert_eq!(error, SpecError::InvalidKind("--all".to_string(), "switch".to_string()));
+        assert_eq!(error.to_string(), "The parameter '--all' has an invalid kind 'switch'.");
+
+        let json = r#"{"commands": [{"name": "show", "colour": "red"}]}"#;
+        assert_eq!(
+            CommandTree::from_json(json).unwrap_err(),
+            SpecError::UnknownField("colour".to_string())
+        );
+        let json = r#"{"commands": [{"name": "show", "parameters": [{"name": "a", "b": 1}]}]}"#;
+        assert_eq!(
+            CommandTree::from_json(json).unwrap_err(),
+            SpecError::UnknownField("b".to_string())
+        );
+
+        let json = r#"{"commands": [{"name": "greet", "help": "Say \"hi\"."}]}"#;
+        let error = CommandTree::from_json(json).unwrap_err();
+        assert_eq!(error, SpecError::EscapedString("Say \"hi\".".to_string()));
+        assert_eq!(error.to_string(), "The string 'Say \"hi\".' contains an escape sequence.");
+    }
+
+    #[test]
+    fn complete_line_replace_span() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag)),
+        );
+        tree.command(Command::new("set"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let completions = parser.complete_line("sho", 3).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].options[0].option_string, "show");
+        assert_eq!(completions[0].replace_span, 0..3);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let completions = parser.complete_line("show --ver", 10).unwrap();
+        assert_eq!(completions[0].options[0].option_string, "--verbose");
+        assert_eq!(completions[0].replace_span, 5..10);
+
+        let mut parser = Parser::new(root);
+        let completions = parser.complete_line("show ", 5).unwrap();
+        assert_eq!(completions[0].options[0].option_string, "--verbose");
+        assert_eq!(completions[0].replace_span, 5..5);
+    }
+
+    #[test]
+    fn parse_recovering_collects_errors() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("ping")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--count").kind(ParameterKind::Named))
+                .parameter(Parameter::new("--quiet").kind(ParameterKind::Flag)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        let errors = parser.parse_recovering(tokenize("ping --cuont 3 --quiet --loud").unwrap());
+        let texts = errors
+            .iter()
+            .map(|e| match *e {
+                ParseError::NoMatches(token, _) => token.text,
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["--cuont", "3", "--loud"]);
+        assert!(parser.parameters.contains_key("--quiet"));
+        assert_eq!(parser.recovered_errors().len(), 3);
+
+        let mut parser = Parser::new(root);
+        assert!(parser.parse_recovering(tokenize("ping --count 3").unwrap()).is_empty());
+        assert_eq!(parser.parameters["--count"], "3");
+        assert!(parser.parse(tokenize("--bogus").unwrap()).is_err());
+    }
+
+    #[test]
+    fn list_separated_values() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("tag")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--tags").kind(ParameterKind::Named).list_separator(',')),
+        );
+        let root = tree.finalize();
+        let values = |line| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(line).unwrap()).unwrap();
+            match parser.parameters().remove("--tags") {
+                Some(ParameterValue::Multiple(values)) => values,
+                _ => panic!(),
             }
+        };
+        assert_eq!(values("tag --tags red,green,blue"), vec!["red", "green", "blue"]);
+        assert_eq!(values("tag --tags red"), vec!["red"]);
+        assert_eq!(values("tag --tags red,green,"), vec!["red", "green"]);
+        assert_eq!(values("tag --tags a,,b"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn parsing_shares_tree() {
+        let names = (0..500).map(|i| format!("command{}", i)).collect::<Vec<_>>();
+        let mut tree = CommandTree::new();
+        for name in &names {
+            tree.command(
+                Command::new(name)
+                    .handler(|_| ExecStatus::Continue)
+                    .parameter(Parameter::new("--count").kind(ParameterKind::Named)),
+            );
         }
+        let root = tree.finalize();
+        let target = Rc::clone(&root.successors()[250]);
+        let root_count = Rc::strong_count(&root);
+        let target_count = Rc::strong_count(&target);
+
+        let parse = |parser: &mut Parser| {
+            parser.parse(tokenize("command250 --count 3").unwrap()).unwrap();
+            (
+                parser.command_path().iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+                parser.parameters.clone(),
+                parser.nodes.len(),
+            )
+        };
+        let mut parser = Parser::new(Rc::clone(&root));
+        let first = parse(&mut parser);
+        parser.reset();
+        let second = parse(&mut parser);
+        assert_eq!(first, second);
+        assert_eq!(first, parse(&mut Parser::new(Rc::clone(&root))));
+        assert_eq!(first.0, vec!["command250"]);
+
+        drop(parser);
+        assert_eq!(Rc::strong_count(&root), root_count);
+        assert_eq!(Rc::strong_count(&target), target_count);
     }
 
     #[test]
-    #[should_panic]
-    fn parse_signals_ambiguous_match() {
+    fn exact_names_looked_up() {
+        let names = (0..100)
+            .map(|i| (format!("command{}", i), format!("c{}", i)))
+            .collect::<Vec<_>>();
+        let mut tree = CommandTree::new();
+        for &(ref name, ref alias) in &names {
+            tree.command(Command::new(name).alias(alias));
+        }
+        let root = tree.finalize();
+        let path = |input, exact_wins| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.set_exact_wins(exact_wins);
+            parser.parse(tokenize(input).unwrap()).map(|_| parser.command_path().join(" "))
+        };
+
+        assert_eq!(path("command4", true).unwrap(), "command4");
+        assert_eq!(path("c42", true).unwrap(), "command42");
+        assert_eq!(path("command42", true).unwrap(), "command42");
+        assert!(path("command4", false).is_err());
+        assert!(path("comm", true).is_err());
+    }
+
+    #[test]
+    fn expectations_at_cursor() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--host").kind(ParameterKind::Named))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("--debug").kind(ParameterKind::Flag).hidden(true))
+                .parameter(Parameter::new("port")),
+        );
+        tree.command(Command::new("quit"));
+        tree.command(Command::new("secret").hidden(true));
+        let root = tree.finalize();
+
+        let parser = Parser::new(Rc::clone(&root));
+        assert_eq!(
+            parser.expectations(),
+            vec![Expectation::Command(vec!["connect".to_string(), "quit".to_string()])]
+        );
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("connect").unwrap()).unwrap();
+        assert_eq!(
+            parser.expectations(),
+            vec![
+                Expectation::Flag(vec!["--verbose".to_string()]),
+                Expectation::NamedParameter(vec!["--host".to_string()]),
+                Expectation::Value("port".to_string()),
+            ]
+        );
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("connect --host").unwrap()).unwrap();
+        let expectations = parser.expectations();
+        assert_eq!(expectations, vec![Expectation::Value("--host".to_string())]);
+        assert_eq!(expectations[0].to_string(), "the value for --host");
+    }
+
+    #[test]
+    fn completer_trait() {
+        fn complete_with<'text, C: Completer>(
+            completer: &C,
+            line: &'text str,
+        ) -> Vec<Completion<'text>> {
+            completer.complete(line, line.len())
+        }
+
         let mut tree = CommandTree::new();
         tree.command(Command::new("show"));
         tree.command(Command::new("set"));
+        let completer = TreeCompleter::new(tree.finalize());
+        let completions = complete_with(&completer, "sh");
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].options[0].option_string, "show");
+        assert_eq!(completions[0].replace_span, 0..2);
+        assert_eq!(complete_with(&completer, "").len(), 2);
+    }
+
+    #[test]
+    fn duplicate_parameters() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .parameter(Parameter::new("host"))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag).short('v')),
+        );
+        assert!(tree.try_finalize().is_ok());
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .parameter(Parameter::new("host"))
+                .parameter(Parameter::new("host")),
+        );
+        assert_eq!(
+            tree.try_finalize().unwrap_err(),
+            BuildError::DuplicateParameter("connect".to_string(), "host".to_string())
+        );
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("connect")
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag).alias("--loud"))
+                .parameter(Parameter::new("--loud").kind(ParameterKind::Flag)),
+        );
+        let error = tree.try_finalize().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The command 'connect' has more than one parameter '--loud'."
+        );
+    }
+
+    #[test]
+    fn counted_flags() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("run")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(
+                    Parameter::new("--verbose")
+                        .kind(ParameterKind::Flag)
+                        .short('v')
+                        .repeatable(true),
+                )
+                .parameter(Parameter::new("--quiet").kind(ParameterKind::Flag).short('q')),
+        );
+        let root = tree.finalize();
+        let count = |line| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.set_short_clusters(true);
+            parser.parse(tokenize(line).unwrap()).unwrap();
+            parser.occurrences("--verbose")
+        };
+        assert_eq!(count("run"), 0);
+        assert_eq!(count("run -v"), 1);
+        assert_eq!(count("run -v --verbose -v"), 3);
+        assert_eq!(count("run -vvv"), 3);
+        assert_eq!(count("run -vqv"), 2);
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_short_clusters(true);
+        parser.parse(tokenize("run -vvv").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--verbose"], "3");
+
+        let mut parser = Parser::new(root);
+        assert!(parser.parse(tokenize("run -q -q").unwrap()).is_err());
+    }
+
+    #[test]
+    fn named_parameter_aliases() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("paint")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(
+                    Parameter::new("--color")
+                        .kind(ParameterKind::Named)
+                        .alias("--colour")
+                        .short('c'),
+                ),
+        );
+        let root = tree.try_finalize().unwrap();
+        for name in &["--color", "--colour", "-c", "--col"] {
+            let line = format!("paint {} red", name);
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(&line).unwrap()).unwrap();
+            assert_eq!(parser.parameters["--color"], "red");
+            assert_eq!(parser.parameters.len(), 1);
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("paint").unwrap()).unwrap();
+        let completions = parser.complete(Some(tokenize("--c").unwrap()[0]));
+        let options = completions
+            .iter()
+            .flat_map(|c| c.options.iter().filter(|o| o.complete))
+            .map(|o| o.option_string.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(options, vec!["--color"]);
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("paint")
+                .parameter(Parameter::new("--color").kind(ParameterKind::Named).short('c'))
+                .parameter(Parameter::new("--count").kind(ParameterKind::Named).alias("-c")),
+        );
+        assert_eq!(
+            tree.try_finalize().unwrap_err(),
+            BuildError::DuplicateParameter("paint".to_string(), "-c".to_string())
+        );
+    }
+
+    #[test]
+    fn spans_for_highlighting() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--flag").kind(ParameterKind::Named)),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show --flag value").unwrap()).unwrap();
+        let spans = parser
+            .matched_spans()
+            .into_iter()
+            .map(|(span, node)| {
+                let kind = match **node {
+                    Node::Command(_) => "command",
+                    Node::ParameterName(_) => "name",
+                    Node::Parameter(_) => "value",
+                    Node::Root(_) => "root",
+                };
+                (span, kind, node.node().name.clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![
+                (0..4, "command", "show".to_string()),
+                (5..11, "name", "--flag".to_string()),
+                (12..17, "value", "--flag".to_string()),
+            ]
+        );
+        assert!(parser.unmatched_spans().is_empty());
+
+        let mut parser = Parser::new(root);
+        assert!(parser.parse(tokenize("show bogus --flag value").unwrap()).is_err());
+        assert_eq!(parser.matched_spans().len(), 1);
+        assert_eq!(parser.unmatched_spans(), vec![5..10, 11..17, 18..23]);
+    }
+
+    #[test]
+    fn optional_named_values() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("run")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--log").kind(ParameterKind::Named).value_optional(true))
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag))
+                .parameter(Parameter::new("target")),
+        );
+        let root = tree.finalize();
+        let parse = |line| {
+            let mut parser = Parser::new(Rc::clone(&root));
+            parser.parse(tokenize(line).unwrap()).unwrap();
+            assert!(parser.verify().is_ok());
+            parser.parameters
+        };
+
+        assert_eq!(parse("run --log")["--log"], "");
+        assert_eq!(parse("run --log debug")["--log"], "debug");
+        assert_eq!(parse("run --log=-1")["--log"], "-1");
+
+        let parameters = parse("run --log --verbose");
+        assert_eq!(parameters["--log"], "");
+        assert!(parameters.contains_key("--verbose"));
+
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("run").parameter(
+                Parameter::new("--log")
+                    .kind(ParameterKind::Named)
+                    .value_optional(true)
+                    .default_value("info"),
+            ),
+        );
         let mut parser = Parser::new(tree.finalize());
-        if let Ok(tokens) = tokenize("s") {
-            match parser.parse(tokens) {
-                Err(ParseError::AmbiguousMatch(_, _)) => panic!(),
-                _ => {}
+        parser.parse(tokenize("run --log").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--log"], "info");
+    }
+
+    #[test]
+    fn verified_choices() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("paint")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--color").kind(ParameterKind::Named).choices(&[
+                    "red",
+                    "green",
+                    "grey",
+                    "blue",
+                ])),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("paint --color green").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+
+        // A prefix of only one choice is expanded to that choice.
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("paint --color bl").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--color"], "blue");
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("paint --color gre").unwrap()).unwrap();
+        assert_eq!(
+            parser.verify().unwrap_err().to_string(),
+            "The value 'gre' of --color must be one of: red, green, grey, blue."
+        );
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.set_case_insensitive(true);
+        parser.parse(tokenize("paint --color GREEN").unwrap()).unwrap();
+        assert_eq!(parser.parameters["--color"], "green");
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("paint --color").unwrap()).unwrap();
+        let options = |completions: Vec<Completion>| {
+            completions[0]
+                .options
+                .iter()
+                .filter(|o| o.complete)
+                .map(|o| o.option_string.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(options(parser.complete(None)), vec!["red", "green", "grey", "blue"]);
+        assert_eq!(options(parser.complete(Some(tokenize("b").unwrap()[0]))), vec!["blue"]);
+    }
+
+    #[test]
+    fn fallible_handlers() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("fetch")
+                .parameter(Parameter::new("url"))
+                .try_handler(|parser| match parser.parameters.get("url") {
+                    Some(url) if url.starts_with("http") => Ok(ExecStatus::Continue),
+                    _ => Err("Unsupported scheme.".to_string()),
+                }),
+        );
+        tree.command(Command::new("noop"));
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("fetch http://example.com").unwrap()).unwrap();
+        assert_eq!(parser.try_execute(), Ok(ExecStatus::Continue));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("fetch ftp://example.com").unwrap()).unwrap();
+        let error = parser.try_execute().unwrap_err();
+        assert_eq!(error.command, "fetch");
+        assert_eq!(error.message, "Unsupported scheme.");
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        match parser.run("fetch gopher://example.com") {
+            Err(CommandError::Exec(ref error)) => {
+                assert_eq!(error.to_string(), "fetch: Unsupported scheme.")
             }
+            _ => panic!(),
+        }
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("noop").unwrap()).unwrap();
+        assert_eq!(parser.try_execute(), Ok(ExecStatus::Continue));
+    }
+
+    #[test]
+    fn execute_continues_on_failure() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("fetch").try_handler(|_| Err("Unsupported scheme.".to_string())));
+        let mut parser = Parser::new(tree.finalize());
+        parser.parse(tokenize("fetch").unwrap()).unwrap();
+        assert_eq!(parser.execute(), ExecStatus::Continue);
+        assert!(parser.try_execute().is_err());
+    }
+
+    #[test]
+    fn typed_getters() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("resize")
+                .parameter(Parameter::new("width").value_type(ValueType::Integer))
+                .parameter(Parameter::new("label"))
+                .parameter(Parameter::new("--force").kind(ParameterKind::Flag))
+                .parameter(
+                    Parameter::new("--wrap")
+                        .kind(ParameterKind::Named)
+                        .value_type(ValueType::Boolean),
+                ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("resize 80 main --force --wrap off").unwrap()).unwrap();
+        assert_eq!(parser.get_i64("width"), Some(80));
+        assert_eq!(parser.get_str("label"), Some("main"));
+        assert_eq!(parser.get_bool("--force"), Some(true));
+        assert_eq!(parser.get_bool("--wrap"), Some(false));
+        // Values of other types aren't converted.
+        assert_eq!(parser.get_i64("label"), None);
+        assert_eq!(parser.get_bool("width"), None);
+        assert_eq!(parser.get_str("width"), Some("80"));
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("resize").unwrap()).unwrap();
+        assert_eq!(parser.get_i64("width"), None);
+        assert_eq!(parser.get_str("label"), None);
+        assert_eq!(parser.get_bool("--force"), None);
+        assert_eq!(parser.get_bool("--wrap"), None);
+    }
+
+    #[test]
+    fn macro_built_commands() {
+        let macro_root = command_tree![
+            command!(
+                "show",
+                help = "Show an item.",
+                params = [
+                    flag!("--all", short = 'a'),
+                    parameter!(
+                        "--format",
+                        kind = ParameterKind::Named,
+                        choices = &["json", "text"]
+                    ),
+                    parameter!("item", required = true),
+                ],
+            ),
+            command!("quit", hidden = true),
+        ];
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .help("Show an item.")
+                .parameter(Parameter::new("--all").kind(ParameterKind::Flag).short('a'))
+                .parameter(
+                    Parameter::new("--format")
+                        .kind(ParameterKind::Named)
+                        .choices(&["json", "text"]),
+                )
+                .parameter(Parameter::new("item").required(true)),
+        );
+        tree.command(Command::new("quit").hidden(true));
+        let builder_root = tree.finalize();
+
+        for line in &["show -a --format json widget", "sh --form text gadget", "quit"] {
+            let mut from_macro = Parser::new(Rc::clone(&macro_root));
+            from_macro.parse(tokenize(line).unwrap()).unwrap();
+            let mut from_builder = Parser::new(Rc::clone(&builder_root));
+            from_builder.parse(tokenize(line).unwrap()).unwrap();
+            assert_eq!(from_macro.command_path(), from_builder.command_path());
+            assert_eq!(from_macro.parameters(), from_builder.parameters());
+            assert_eq!(from_macro.to_command_line(), from_builder.to_command_line());
+            assert_eq!(from_macro.verify().is_ok(), from_builder.verify().is_ok());
         }
+        let macro_names: Vec<String> =
+            Parser::new(macro_root).complete(None).into_iter().map(|c| c.help_symbol).collect();
+        let builder_names: Vec<String> =
+            Parser::new(builder_root).complete(None).into_iter().map(|c| c.help_symbol).collect();
+        assert_eq!(macro_names, builder_names);
+    }
+
+    #[test]
+    fn extended_trees() {
+        let mut host = CommandTree::new();
+        host.command(Command::new("show"));
+        let mut plugin = CommandTree::new();
+        plugin.default_priority(PRIORITY_MINIMUM);
+        plugin.command(Command::new("shout"));
+        plugin.lazy_command("reload", Box::new(|| Command::new("reload")));
+        host.extend(plugin);
+        let root = host.try_finalize().unwrap();
+        let names: Vec<&str> =
+            root.successors().iter().map(|n| n.node().name.as_str()).collect();
+        assert_eq!(names, vec!["show", "shout", "reload"]);
+        // The plugin's priority is kept, so its command loses out
+        // to the host's when both match.
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("sho").unwrap()).unwrap();
+        assert_eq!(parser.command_path(), vec!["show"]);
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("reload").unwrap()).unwrap();
+        assert_eq!(parser.command_path(), vec!["reload"]);
+
+        let mut host = CommandTree::new();
+        host.command(Command::new("show"));
+        let mut plugin = CommandTree::new();
+        plugin.command(Command::new("list").alias("show"));
+        host.extend(plugin);
+        assert_eq!(host.try_finalize().unwrap_err(), BuildError::DuplicateCommand("show".into()));
+    }
+
+    #[test]
+    fn completing_hidden_nodes() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(
+            Command::new("debug")
+                .hidden(true)
+                .parameter(Parameter::new("--verbose").kind(ParameterKind::Flag).hidden(true)),
+        );
+        let root = tree.finalize();
+
+        let parser = Parser::new(Rc::clone(&root));
+        let names: Vec<String> = parser.complete(None).into_iter().map(|c| c.help_symbol).collect();
+        assert_eq!(names, vec!["show"]);
+
+        assert_eq!(parser.expectations(), vec![Expectation::Command(vec!["show".to_string()])]);
+
+        let mut parser = Parser::new(root);
+        parser.set_show_hidden(true);
+        assert_eq!(
+            parser.expectations(),
+            vec![Expectation::Command(vec!["debug".to_string(), "show".to_string()])]
+        );
+        let completions = parser.complete(None);
+        let names: Vec<(&str, bool)> =
+            completions.iter().map(|c| (c.help_symbol.as_str(), c.hidden)).collect();
+        assert_eq!(names, vec![("debug", true), ("show", false)]);
+        parser.parse(tokenize("debug").unwrap()).unwrap();
+        let completions = parser.complete(None);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].help_symbol, "[--verbose]");
+        assert!(completions[0].hidden);
+    }
+
+    #[test]
+    fn fuzzy_completion() {
+        let mut tree = CommandTree::new();
+        tree.command(Command::new("show"));
+        tree.command(Command::new("shutdown"));
+        tree.command(Command::new("set-window").alias("sw"));
+        tree.command(Command::new("quit"));
+        let mut parser = Parser::new(tree.finalize());
+        let tokens = tokenize("shw").unwrap();
+
+        assert!(parser.complete(Some(tokens[0])).is_empty());
+        let completions = parser.complete_fuzzy(Some(tokens[0]));
+        let names: Vec<&str> = completions.iter().map(|c| c.help_symbol.as_str()).collect();
+        assert_eq!(names, vec!["show", "shutdown"]);
+        let options: Vec<(&str, bool)> = completions[0]
+            .options
+            .iter()
+            .map(|o| (o.option_string.as_str(), o.complete))
+            .collect();
+        assert_eq!(options, vec![("show", true)]);
+        assert_eq!(completions[0].replace_span, 0..3);
+
+        // An alias can match better than a name.
+        let tokens = tokenize("sw").unwrap();
+        let completions = parser.complete_fuzzy(Some(tokens[0]));
+        let names: Vec<&str> = completions.iter().map(|c| c.help_symbol.as_str()).collect();
+        assert_eq!(names, vec!["set-window", "show", "shutdown"]);
+
+        // Parsing is unchanged.
+        assert!(parser.parse(tokenize("shw").unwrap()).is_err());
+    }
+
+    #[test]
+    fn verify_parent_commands() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("show")
+                .handler(|_| ExecStatus::Continue)
+                .parameter(Parameter::new("--host").kind(ParameterKind::Named).required(true))
+                .subcommand(Command::new("interface").handler(|_| ExecStatus::Continue)),
+        );
+        tree.command(
+            Command::new("set")
+                .parameter(Parameter::new("--host").kind(ParameterKind::Named).required(true))
+                .subcommand(Command::new("mtu").handler(|_| ExecStatus::Continue)),
+        );
+        let root = tree.finalize();
+        let missing = |parser: &Parser| match parser.verify() {
+            Err(VerifyError::MissingParameter(ref names)) => names == &["--host"],
+            _ => false,
+        };
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show").unwrap()).unwrap();
+        assert!(missing(&parser));
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("show interface").unwrap()).unwrap();
+        assert!(missing(&parser));
+
+        // The parent is verified even without a handler of its own.
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("set mtu").unwrap()).unwrap();
+        assert!(missing(&parser));
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("show --host a interface").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+    }
+
+    #[test]
+    fn list_separated_values_verified() {
+        let mut tree = CommandTree::new();
+        tree.command(
+            Command::new("tag").handler(|_| ExecStatus::Continue).parameter(
+                Parameter::new("--tags")
+                    .kind(ParameterKind::Named)
+                    .list_separator(',')
+                    .choices(&["red", "green"]),
+            ),
+        );
+        tree.command(
+            Command::new("pick").handler(|_| ExecStatus::Continue).parameter(
+                Parameter::new("--n")
+                    .kind(ParameterKind::Named)
+                    .list_separator(',')
+                    .value_type(ValueType::Integer),
+            ),
+        );
+        let root = tree.finalize();
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("tag --tags red,green").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        assert!(parser.parse(tokenize("tag --tags red,blue").unwrap()).is_err());
+
+        let mut parser = Parser::new(Rc::clone(&root));
+        parser.parse(tokenize("pick --n 1,2").unwrap()).unwrap();
+        assert!(parser.verify().is_ok());
+
+        let mut parser = Parser::new(root);
+        parser.parse(tokenize("pick --n 1,x").unwrap()).unwrap();
+        assert_eq!(
+            parser.verify().unwrap_err().to_string(),
+            "The value 'x' of --n must be an integer."
+        );
     }
 }
@@ -8,11 +8,17 @@
 // by the currently permissible set of commands and their
 // parameters.
 
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::rc::Rc;
 
-use super::{Completion, Parser};
+use super::{CommandHandler, Completion, Parser, ValueCompleter};
+use super::builder::BuildWarning;
 use super::constants::*;
+use super::validation::{PathValidation, ValueType};
 use tokenizer::Token;
+use util::{is_prefix, is_prefix_ignoring_case};
 
 /// Enumeration of node types used to have vectors of `Node` and so on.
 pub enum Node {
@@ -45,10 +51,22 @@ pub trait NodeOps {
     /// [`CommandNode`]: struct.CommandNode.html
     fn acceptable(&self, parser: &Parser, node_ref: &Rc<Node>) -> bool;
 
+    /// The [`TreeNode`] data of this node, if it has any.
+    ///
+    /// Nodes which give their data here get the default
+    /// completion of their name from `complete`. This defaults to
+    /// `None`.
+    ///
+    /// [`TreeNode`]: struct.TreeNode.html
+    fn tree_node(&self) -> Option<&TreeNode> {
+        None
+    }
+
     /// Given a node and an optional token, provide the completion options.
     ///
-    /// By default, completion should complete for the name of the given
-    /// node.
+    /// By default, completion completes the name of the node given
+    /// by `tree_node`, filtered by the `token` when one is given.
+    /// A node without a `tree_node` has no completion options.
     ///
     /// This is the expected behavior for [`CommandNode`],
     /// [`ParameterNameNode`], as well as [`ParameterNode`] where the
@@ -58,7 +76,12 @@ pub trait NodeOps {
     /// [`ParameterKind`]: enum.ParameterKind.html
     /// [`ParameterNameNode`]: struct.ParameterNameNode.html
     /// [`ParameterNode`]: struct.ParameterNode.html
-    fn complete<'text>(&self, token: Option<Token<'text>>) -> Completion<'text>;
+    fn complete<'text>(&self, token: Option<Token<'text>>) -> Completion<'text> {
+        match self.tree_node() {
+            Some(node) => complete_name(node, token),
+            None => Completion::new(String::new(), String::new(), token, true, &[], &[]),
+        }
+    }
 
     /// By default, a node matches a `token` when the name of the
     /// node starts with the `token`.
@@ -78,6 +101,9 @@ pub trait NodeOps {
 pub struct TreeNode {
     /// The name of this node.
     pub name: String,
+    /// Alternate names which match this node when given
+    /// in full.
+    pub aliases: Vec<String>,
     /// The text used to identify this node in help text.
     /// This is typically the node name, either in plain
     /// form or decorated for parameters.
@@ -86,6 +112,9 @@ pub struct TreeNode {
     pub help_text: String,
     /// Hidden nodes are not completed. This doesn't modify matching.
     pub hidden: bool,
+    /// Deprecated nodes still match, but their use is reported
+    /// to the deprecation handler of the `Parser`.
+    pub deprecated: bool,
     /// Match and complete priority.
     pub priority: i32,
     /// Whether or not this node can be repeated. A repeated
@@ -95,14 +124,89 @@ pub struct TreeNode {
     pub repeat_marker: Option<Rc<Node>>,
     /// Possible successor nodes. Collected while building.
     pub successors: Vec<Rc<Node>>,
+    successor_index: OnceCell<HashMap<String, Vec<usize>>>,
+}
+
+impl TreeNode {
+    /// The successors of this node which are matched by name and
+    /// have `name` as their name or one of their aliases.
+    ///
+    /// These are looked up from an index which is built for each
+    /// node of the tree by `CommandTree::finalize`, or otherwise
+    /// when first needed.
+    pub fn successors_named<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a Rc<Node>> {
+        let indices = self.successor_index().get(name).map_or(&[][..], |i| &i[..]);
+        indices.iter().map(move |&i| &self.successors[i])
+    }
+
+    fn successor_index(&self) -> &HashMap<String, Vec<usize>> {
+        self.successor_index.get_or_init(|| {
+            let mut index = HashMap::<String, Vec<usize>>::new();
+            for (i, successor) in self.successors.iter().enumerate() {
+                if successor.matches_by_name() {
+                    let node = successor.node();
+                    for name in Some(&node.name).into_iter().chain(&node.aliases) {
+                        index.entry(name.clone()).or_default().push(i);
+                    }
+                }
+            }
+            index
+        })
+    }
+
+    /// Build the index of successors by name for this node and
+    /// every node which may follow it.
+    pub fn index_successors(&self) {
+        let mut visited = HashSet::new();
+        index_successors(self, &mut visited);
+    }
+
+    /// Whether `text` is the name of this node or one of its
+    /// aliases.
+    pub fn is_named(&self, text: &str) -> bool {
+        self.name == text || self.aliases.iter().any(|a| a == text)
+    }
+
+    /// Whether `text` is a prefix of the name of this node or
+    /// is one of its aliases.
+    pub fn matches_name(&self, text: &str) -> bool {
+        is_prefix(&self.name, text) || self.aliases.iter().any(|a| a == text)
+    }
+
+    /// Whether `text` is the name of this node or one of its
+    /// aliases, ignoring ASCII case.
+    pub fn is_named_ignoring_case(&self, text: &str) -> bool {
+        self.name.eq_ignore_ascii_case(text) ||
+            self.aliases.iter().any(|a| a.eq_ignore_ascii_case(text))
+    }
+
+    /// Whether `text` is a prefix of the name of this node or
+    /// is one of its aliases, ignoring ASCII case.
+    pub fn matches_name_ignoring_case(&self, text: &str) -> bool {
+        is_prefix_ignoring_case(&self.name, text) ||
+            self.aliases.iter().any(|a| a.eq_ignore_ascii_case(text))
+    }
 }
 
+/// Produces the message for an unknown command from the text
+/// which didn't match.
+pub type UnknownCommandMessage = Rc<dyn Fn(&str) -> String>;
+
 /// The root of a command tree.
 pub struct RootNode {
     /// [`TreeNode`] data.
     ///
     /// [`TreeNode`]: struct.TreeNode.html
     pub node: TreeNode,
+    /// Aliases which expand to a whole command path, keyed by
+    /// the alias.
+    pub path_aliases: HashMap<String, Vec<String>>,
+    /// Problems found while building the tree which did not
+    /// prevent it from being built.
+    pub warnings: Vec<BuildWarning>,
+    /// Produces the message for a command which doesn't match,
+    /// given the text of the unmatched token.
+    pub unknown_command_message: Option<UnknownCommandMessage>,
 }
 
 /// A node representing a command. Constructed via [`Command`] and [`CommandTree`].
@@ -119,11 +223,55 @@ pub struct CommandNode {
     /// [`TreeNode`]: struct.TreeNode.html
     pub node: TreeNode,
     /// The handler which is executed once this node has been accepted.
-    pub handler: Option<fn(node: &Node) -> ()>,
+    pub handler: Option<CommandHandler>,
     /// Parameter nodes for this command
     pub parameters: Vec<Rc<Node>>,
     /// If present, the command wrapped by this node.
     pub wrapped_root: Option<Rc<Node>>,
+    /// Groups of parameters, by name, of which at most one
+    /// may be given.
+    pub exclusive_groups: Vec<Vec<String>>,
+    /// If present, the flag which must be given to confirm
+    /// that the command should be executed.
+    pub required_token: Option<String>,
+    /// Text shown in help before the parameters of the command.
+    pub help_header: Option<String>,
+    /// Text shown in help after the parameters of the command.
+    pub help_footer: Option<String>,
+    /// If present, this node stands in for a command whose
+    /// definition is built when it is first needed.
+    pub lazy: Option<LazyDefinition>,
+    see_also: Vec<String>,
+}
+
+/// The definition of a command which is built when it is first
+/// matched or completed, rather than when the tree is built.
+///
+/// This is created by `CommandTree::lazy_command`.
+pub struct LazyDefinition {
+    build: Box<dyn Fn() -> CommandNode>,
+    definition: OnceCell<Rc<Node>>,
+}
+
+impl LazyDefinition {
+    /// Construct a new `LazyDefinition` which is built by `build`.
+    pub fn new(build: Box<dyn Fn() -> CommandNode>) -> Self {
+        LazyDefinition {
+            build: build,
+            definition: OnceCell::new(),
+        }
+    }
+
+    /// Get the definition, building it if this is the first time
+    /// that it has been needed.
+    pub fn definition(&self) -> &Rc<Node> {
+        self.definition.get_or_init(|| Rc::new(Node::Command((self.build)())))
+    }
+
+    /// Whether or not the definition has been built.
+    pub fn is_built(&self) -> bool {
+        self.definition.get().is_some()
+    }
 }
 
 /// A node that represented the name portion of a named
@@ -148,6 +296,162 @@ pub struct ParameterNode {
     pub required: bool,
     /// What type of `ParameterKind` this is.
     pub kind: ParameterKind,
+    /// The values which this parameter accepts. When empty,
+    /// any value is accepted.
+    pub choices: Vec<String>,
+    /// How the value should be validated as a path.
+    pub path_validation: PathValidation,
+    /// The type that the value must be parseable as.
+    pub value_type: ValueType,
+    /// A greedy parameter takes the rest of the command line
+    /// as its value.
+    pub greedy: bool,
+    /// A negatable flag is recorded as `true` when it is given, and
+    /// may be set to `false` by giving its negation, as in `--no-color`
+    /// for `--color`.
+    pub negatable: bool,
+    /// If present, this flag is the negation of the flag with this
+    /// name, which it sets to `false`.
+    pub negates: Option<String>,
+    /// Experimental parameters are only available when they
+    /// have been enabled on the `Parser`.
+    pub experimental: bool,
+    /// The slot shared by the members of a choice, of which this
+    /// parameter is one.
+    pub slot: Option<String>,
+    /// The value of the parameter when it isn't given.
+    pub default_value: Option<String>,
+    /// If present, the key in the context of the `Parser` from
+    /// which the value is taken when it isn't given.
+    pub context_key: Option<String>,
+    /// The fewest times that a repeatable parameter must be given,
+    /// if it is given at all.
+    pub min_occurs: usize,
+    /// If present, the most times that a repeatable parameter may
+    /// be given.
+    pub max_occurs: Option<usize>,
+    /// Parameters which must also be given when this one is.
+    pub requires: Vec<String>,
+    /// Parameters which may not be given along with this one.
+    pub conflicts_with: Vec<String>,
+    /// If present, provides values to offer when completing
+    /// the value of this parameter.
+    pub completer: Option<ValueCompleter>,
+    /// If present, the value is split on this separator into
+    /// several values, dropping any which are empty.
+    pub list_separator: Option<char>,
+    /// Whether a named parameter may be given without a value.
+    pub value_optional: bool,
+}
+
+/// Formats a list of nodes by their names, so that formatting
+/// a node doesn't recurse through the whole tree.
+struct NodeNames<'a>(&'a [Rc<Node>]);
+
+impl<'a> fmt::Debug for NodeNames<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_list().entries(self.0.iter().map(|n| &n.node().name)).finish()
+    }
+}
+
+fn index_successors(node: &TreeNode, visited: &mut HashSet<*const TreeNode>) {
+    if visited.insert(node as *const _) {
+        node.successor_index();
+        for successor in &node.successors {
+            index_successors(successor.node(), visited);
+        }
+    }
+}
+
+/// Complete the name of a `node`, filtered by the `token` when
+/// one is given.
+fn complete_name<'text>(node: &TreeNode, token: Option<Token<'text>>) -> Completion<'text> {
+    Completion::new(
+        node.help_symbol.clone(),
+        node.help_text.clone(),
+        token,
+        true,
+        &[&node.name],
+        &[],
+    )
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Node::Command(ref command) => command.fmt(f),
+            Node::Parameter(ref parameter) => parameter.fmt(f),
+            Node::ParameterName(ref name) => name.fmt(f),
+            Node::Root(ref root) => root.fmt(f),
+        }
+    }
+}
+
+impl fmt::Debug for TreeNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("TreeNode")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("hidden", &self.hidden)
+            .field("deprecated", &self.deprecated)
+            .field("priority", &self.priority)
+            .field("repeatable", &self.repeatable)
+            .field("successors", &NodeNames(&self.successors))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RootNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RootNode")
+            .field("commands", &NodeNames(&self.node.successors))
+            .field("path_aliases", &self.path_aliases)
+            .field("warnings", &self.warnings)
+            .finish()
+    }
+}
+
+impl fmt::Debug for CommandNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("CommandNode")
+            .field("node", &self.node)
+            .field("has_handler", &self.handler.is_some())
+            .field("parameters", &NodeNames(&self.parameters))
+            .field("exclusive_groups", &self.exclusive_groups)
+            .field("required_token", &self.required_token)
+            .field("lazy", &self.lazy)
+            .finish()
+    }
+}
+
+impl fmt::Debug for LazyDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("LazyDefinition").field("is_built", &self.is_built()).finish()
+    }
+}
+
+impl fmt::Debug for ParameterNameNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ParameterNameNode")
+            .field("node", &self.node)
+            .field("parameter", &self.parameter.node().name)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ParameterNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ParameterNode")
+            .field("node", &self.node)
+            .field("kind", &self.kind)
+            .field("required", &self.required)
+            .field("choices", &self.choices)
+            .field("value_type", &self.value_type)
+            .field("greedy", &self.greedy)
+            .field("slot", &self.slot)
+            .field("default_value", &self.default_value)
+            .finish()
+    }
 }
 
 impl PartialEq for Node {
@@ -175,6 +479,15 @@ impl Node {
         }
     }
 
+    /// Whether or not this node is matched by its name, rather
+    /// than matching a value as named and simple parameters do.
+    pub fn matches_by_name(&self) -> bool {
+        match *self {
+            Node::Parameter(ref parameter) => parameter.kind == ParameterKind::Flag,
+            _ => true,
+        }
+    }
+
     /// Get or calculate successors of this node.
     pub fn successors(&self) -> &Vec<Rc<Node>> {
         match *self {
@@ -185,6 +498,10 @@ impl Node {
 }
 
 impl NodeOps for Node {
+    fn tree_node(&self) -> Option<&TreeNode> {
+        Some(self.node())
+    }
+
     fn accept<'text>(&self, parser: &mut Parser<'text>, token: Token, node_ref: &Rc<Node>) {
         match *self {
             Node::Command(ref command) => command.accept(parser, token, node_ref),
@@ -228,14 +545,142 @@ impl RootNode {
         RootNode {
             node: TreeNode {
                 name: "__root__".to_string(),
+                aliases: vec![],
                 help_symbol: "".to_string(),
                 help_text: "".to_string(),
                 hidden: false,
+                deprecated: false,
                 priority: PRIORITY_DEFAULT,
                 repeat_marker: None,
                 repeatable: false,
                 successors: successors,
+                successor_index: OnceCell::new(),
             },
+            path_aliases: HashMap::new(),
+            warnings: vec![],
+            unknown_command_message: None,
+        }
+    }
+
+    /// Collect every flag parameter in the tree along with the
+    /// path of command names leading to the command that takes it.
+    ///
+    /// This is useful for generating a reference of the options
+    /// which are common across commands.
+    pub fn all_flags(&self) -> Vec<(Vec<String>, &ParameterNode)> {
+        let mut flags = vec![];
+        collect_flags(&self.node.successors, &mut vec![], &mut flags);
+        flags
+    }
+
+    /// Render a listing of the commands in the tree, giving the
+    /// help text of each command alongside its name.
+    ///
+    /// Hidden commands are not included.
+    pub fn help_listing(&self) -> String {
+        self.listing(false)
+    }
+
+    /// Render a listing of the commands in the tree, as with
+    /// `help_listing`, including hidden commands, which are
+    /// marked as `(hidden)`.
+    ///
+    /// This is meant for debugging tools, along with
+    /// `Parser::set_show_hidden`.
+    pub fn help_listing_with_hidden(&self) -> String {
+        self.listing(true)
+    }
+
+    fn listing(&self, show_hidden: bool) -> String {
+        let commands = self.node
+            .successors
+            .iter()
+            .map(|n| n.node())
+            .filter(|n| show_hidden || !n.hidden)
+            .collect::<Vec<_>>();
+        let width = commands.iter().map(|n| n.help_symbol.chars().count()).max().unwrap_or(0);
+        let mut listing = String::from("Commands:\n");
+        for command in commands {
+            listing.push_str(&format!(
+                "  {:width$}  {}\n",
+                command.help_symbol,
+                help_text_marking_hidden(command),
+                width = width
+            ));
+        }
+        listing
+    }
+
+    /// Render the help for the command at the end of `path`, as
+    /// given by `CommandNode::help`. The usage line starts with the
+    /// commands leading to it, as in `show interface <name>`.
+    ///
+    /// Each name in the `path` must be the full name or an alias
+    /// of a command. This gives `None` when there is no such
+    /// command.
+    pub fn command_help(&self, path: &[&str]) -> Option<String> {
+        self.help_for(path, false)
+    }
+
+    /// Render the help for the command at the end of `path`, as
+    /// with `command_help`, including its hidden parameters and
+    /// subcommands, as given by `CommandNode::help_with_hidden`.
+    pub fn command_help_with_hidden(&self, path: &[&str]) -> Option<String> {
+        self.help_for(path, true)
+    }
+
+    fn help_for(&self, path: &[&str], show_hidden: bool) -> Option<String> {
+        let mut successors = &self.node.successors;
+        let mut command = None;
+        let mut parents = vec![];
+        for name in path {
+            let node = successors.iter().find(|n| match ***n {
+                Node::Command(ref c) => c.node.is_named(name),
+                _ => false,
+            })?;
+            let node = match **node {
+                Node::Command(CommandNode { lazy: Some(ref lazy), .. }) => lazy.definition(),
+                _ => node,
+            };
+            successors = node.successors();
+            if let Some(parent) = command.replace(node) {
+                parents.push(parent.node().help_symbol.as_str());
+            }
+        }
+        match **command? {
+            Node::Command(ref command) => Some(command.help_within(&parents, show_hidden)),
+            _ => None,
+        }
+    }
+}
+
+/// The help text of a node, followed by `(hidden)` when the
+/// node is hidden.
+fn help_text_marking_hidden(node: &TreeNode) -> String {
+    match (node.hidden, node.help_text.is_empty()) {
+        (false, _) => node.help_text.clone(),
+        (true, true) => "(hidden)".to_string(),
+        (true, false) => format!("{} (hidden)", node.help_text),
+    }
+}
+
+fn collect_flags<'a>(
+    nodes: &'a [Rc<Node>],
+    path: &mut Vec<String>,
+    flags: &mut Vec<(Vec<String>, &'a ParameterNode)>,
+) {
+    for node in nodes {
+        if let Node::Command(ref command) = **node {
+            path.push(command.node.name.clone());
+            for parameter in &command.parameters {
+                if let Node::Parameter(ref p) = **parameter {
+                    if p.kind == ParameterKind::Flag {
+                        flags.push((path.clone(), p));
+                    }
+                }
+            }
+            collect_flags(&command.node.successors, path, flags);
+            path.pop();
         }
     }
 }
@@ -243,6 +688,10 @@ impl RootNode {
 /// `RootNode` does not want to perform any actual `NodeOps` as these
 /// operations should only be invoked by the `Parser` on successor nodes.
 impl NodeOps for RootNode {
+    fn tree_node(&self) -> Option<&TreeNode> {
+        Some(&self.node)
+    }
+
     fn accept<'text>(&self, _parser: &mut Parser<'text>, _token: Token, _node_ref: &Rc<Node>) {}
 
     fn acceptable(&self, _parser: &Parser, _node_ref: &Rc<Node>) -> bool {
@@ -268,28 +717,229 @@ impl CommandNode {
         hidden: bool,
         priority: i32,
         successors: Vec<Rc<Node>>,
-        handler: Option<fn(node: &Node) -> ()>,
+        handler: Option<CommandHandler>,
         parameters: Vec<Rc<Node>>,
+        see_also: &[&str],
     ) -> Self {
         CommandNode {
             node: TreeNode {
                 name: name.to_string(),
+                aliases: vec![],
                 help_symbol: name.to_string(),
                 help_text: help_text.unwrap_or("Command").to_string(),
                 hidden: hidden,
+                deprecated: false,
                 priority: priority,
                 repeat_marker: None,
                 repeatable: false,
                 successors: successors,
+                successor_index: OnceCell::new(),
             },
             handler: handler,
             parameters: parameters,
             wrapped_root: None,
+            exclusive_groups: vec![],
+            required_token: None,
+            help_header: None,
+            help_footer: None,
+            lazy: None,
+            see_also: see_also.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// The names of related commands which help may cross-reference.
+    pub fn see_also(&self) -> &[String] {
+        &self.see_also
+    }
+
+    /// The flag parameters of this command, in the order in which
+    /// they were declared.
+    pub fn flag_parameters(&self) -> Vec<&ParameterNode> {
+        self.parameters_of_kind(ParameterKind::Flag)
+    }
+
+    /// The named parameters of this command, which take a value,
+    /// in the order in which they were declared.
+    pub fn named_parameters(&self) -> Vec<&ParameterNode> {
+        self.parameters_of_kind(ParameterKind::Named)
+    }
+
+    /// The simple, positional, parameters of this command, in the
+    /// order in which they were declared.
+    pub fn simple_parameters(&self) -> Vec<&ParameterNode> {
+        self.parameters_of_kind(ParameterKind::Simple)
+    }
+
+    fn parameters_of_kind(&self, kind: ParameterKind) -> Vec<&ParameterNode> {
+        self.parameters
+            .iter()
+            .filter_map(|p| match **p {
+                Node::Parameter(ref p) if p.kind == kind => Some(p),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The parameters of this command in the order that the parser
+    /// considers them.
+    ///
+    /// Flags and named parameters are matched by name, so they may
+    /// be given anywhere after the command, interspersed with the
+    /// simple parameters. They come first, in the order in which
+    /// they were declared. Simple parameters are positional and are
+    /// bound to values in the order in which they were declared, so
+    /// they follow in that order.
+    pub fn parameters_in_order(&self) -> Vec<&ParameterNode> {
+        let parameters = self.parameters.iter().filter_map(|p| match **p {
+            Node::Parameter(ref p) => Some(p),
+            _ => None,
+        });
+        let (simple, by_name): (Vec<_>, Vec<_>) =
+            parameters.partition(|p| p.kind == ParameterKind::Simple);
+        by_name.into_iter().chain(simple).collect()
+    }
+
+    /// Render a usage line for this command.
+    ///
+    /// The usage line gives the help symbol of the command, which is
+    /// usually its name, followed by the help symbols of its
+    /// parameters. Subcommands are rendered with their own
+    /// parameters. When there is more than one subcommand, they are
+    /// given as alternatives, as in `show {interface <name> [--brief] | route}`.
+    ///
+    /// Hidden parameters and subcommands are not included.
+    pub fn grammar(&self) -> String {
+        self.grammar_showing(false)
+    }
+
+    fn grammar_showing(&self, show_hidden: bool) -> String {
+        let mut grammar = self.usage_line(show_hidden);
+        let subcommands = self.node
+            .successors
+            .iter()
+            .filter(|n| show_hidden || !n.node().hidden)
+            .filter_map(|n| match **n {
+                Node::Command(ref command) => Some(command.usage_line(show_hidden)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match subcommands.len() {
+            0 => {}
+            1 => grammar = grammar + " " + &subcommands[0],
+            _ => grammar = grammar + " {" + &subcommands.join(" | ") + "}",
         }
+        grammar
+    }
+
+    /// Render the help for this command.
+    ///
+    /// This gives the usage line of the command and a list of its
+    /// parameters with their help text, surrounded by the help
+    /// header and footer when they have been given.
+    pub fn help(&self) -> String {
+        self.help_within(&[], false)
+    }
+
+    /// Render the help for this command, as with `help`, including
+    /// its hidden parameters and subcommands. Hidden parameters are
+    /// marked as `(hidden)` in the list of parameters.
+    pub fn help_with_hidden(&self) -> String {
+        self.help_within(&[], true)
+    }
+
+    /// Render the help for this command, as with `help`, when it
+    /// is a subcommand of the commands with the help symbols in
+    /// `parents`, which are given in its usage line.
+    fn help_within(&self, parents: &[&str], show_hidden: bool) -> String {
+        let mut help = String::new();
+        if let Some(ref header) = self.help_header {
+            help.push_str(header);
+            help.push_str("\n\n");
+        }
+        help.push_str("Usage: ");
+        for parent in parents {
+            help.push_str(parent);
+            help.push(' ');
+        }
+        help.push_str(&self.grammar_showing(show_hidden));
+        help.push('\n');
+        let parameters = self.parameters_shown(show_hidden);
+        if !parameters.is_empty() {
+            help.push_str("\nParameters:\n");
+            for parameter in parameters {
+                help.push_str(&format!(
+                    "  {}  {}\n",
+                    parameter.help_symbol,
+                    help_text_marking_hidden(parameter)
+                ));
+            }
+        }
+        if let Some(ref footer) = self.help_footer {
+            help.push('\n');
+            help.push_str(footer);
+            help.push('\n');
+        }
+        help
+    }
+
+    /// Terms by which this command can be found when searching,
+    /// such as from a command palette.
+    ///
+    /// These are the name of the command, its aliases and the
+    /// significant words of its help text, lowercased and without
+    /// duplicates. Short words and common words like `the` are
+    /// not considered significant.
+    pub fn search_terms(&self) -> Vec<String> {
+        const COMMON_WORDS: &[&str] = &[
+            "and", "are", "for", "from", "into", "its", "not", "that", "the", "this",
+            "with", "which",
+        ];
+        let mut terms: Vec<String> = vec![];
+        let names = Some(&self.node.name).into_iter().chain(&self.node.aliases);
+        let words = self.node
+            .help_text
+            .split(|c: char| !c.is_alphanumeric() && c != '-')
+            .map(|w| w.trim_matches('-').to_lowercase())
+            .filter(|w| w.chars().count() > 2 && !COMMON_WORDS.contains(&w.as_str()));
+        for term in names.map(|n| n.to_lowercase()).chain(words) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+        terms
+    }
+
+    /// The help symbol of this command followed by those
+    /// of its parameters.
+    fn usage_line(&self, show_hidden: bool) -> String {
+        let mut line = self.node.help_symbol.clone();
+        for parameter in self.parameters_shown(show_hidden) {
+            line.push(' ');
+            line.push_str(&parameter.help_symbol);
+        }
+        line
+    }
+
+    /// The nodes for the parameters of this command which are
+    /// not hidden, unless hidden parameters are being shown.
+    fn parameters_shown(&self, show_hidden: bool) -> Vec<&TreeNode> {
+        self.node
+            .successors
+            .iter()
+            .filter(|n| match ***n {
+                Node::Parameter(_) | Node::ParameterName(_) => show_hidden || !n.node().hidden,
+                _ => false,
+            })
+            .map(|n| n.node())
+            .collect()
     }
 }
 
 impl NodeOps for CommandNode {
+    fn tree_node(&self) -> Option<&TreeNode> {
+        Some(&self.node)
+    }
+
     /// Record this command.
     fn accept<'text>(&self, parser: &mut Parser<'text>, _token: Token, node_ref: &Rc<Node>) {
         if self.handler.is_some() {
@@ -302,18 +952,14 @@ impl NodeOps for CommandNode {
     }
 
     fn complete<'text>(&self, token: Option<Token<'text>>) -> Completion<'text> {
-        Completion::new(
-            self.node.help_symbol.clone(),
-            self.node.help_text.clone(),
-            token,
-            true,
-            &[&self.node.name],
-            &[],
-        )
+        if let Some(ref lazy) = self.lazy {
+            return lazy.definition().complete(token);
+        }
+        complete_name(&self.node, token)
     }
 
-    fn matches(&self, _parser: &Parser, token: Token) -> bool {
-        self.node.name.starts_with(token.text)
+    fn matches(&self, parser: &Parser, token: Token) -> bool {
+        parser.matches_name(&self.node, token.text)
     }
 }
 
@@ -331,27 +977,58 @@ impl ParameterNameNode {
         let param_node = &parameter.node();
         let help_text = param_node.help_text.clone();
         let help_symbol = name.to_string() + " " + param_node.help_symbol.as_str();
+        let help_symbol = match *parameter {
+            Node::Parameter(ref p) if p.required => help_symbol,
+            _ => String::from("[") + &help_symbol + "]",
+        };
         ParameterNameNode {
             node: TreeNode {
                 name: name.to_string(),
+                aliases: vec![],
                 help_symbol: help_symbol,
                 help_text: help_text,
                 hidden: hidden,
+                deprecated: false,
                 priority: priority,
                 repeat_marker: repeat_marker,
                 repeatable: repeatable,
                 successors: successors,
+                successor_index: OnceCell::new(),
             },
             parameter: Rc::clone(&parameter),
         }
     }
 }
 
+impl ParameterNameNode {
+    fn experimental(&self) -> bool {
+        match *self.parameter {
+            Node::Parameter(ref p) => p.experimental,
+            _ => false,
+        }
+    }
+}
+
 impl NodeOps for ParameterNameNode {
-    /// Record this command.
-    fn accept<'text>(&self, _parser: &mut Parser<'text>, _token: Token, _node_ref: &Rc<Node>) {}
+    fn tree_node(&self) -> Option<&TreeNode> {
+        Some(&self.node)
+    }
+
+    /// Record a parameter whose value is optional as given, in
+    /// case its value doesn't follow.
+    fn accept<'text>(&self, parser: &mut Parser<'text>, _token: Token, _node_ref: &Rc<Node>) {
+        if let Node::Parameter(ref p) = *self.parameter {
+            if p.value_optional {
+                let value = p.default_value.clone().unwrap_or_default();
+                parser.parameters.insert(p.node.name.clone(), value);
+            }
+        }
+    }
 
     fn acceptable(&self, parser: &Parser, node_ref: &Rc<Node>) -> bool {
+        if self.experimental() && !parser.experimental {
+            return false;
+        }
         if self.node.repeatable {
             return true;
         }
@@ -362,19 +1039,8 @@ impl NodeOps for ParameterNameNode {
             }
     }
 
-    fn complete<'text>(&self, token: Option<Token<'text>>) -> Completion<'text> {
-        Completion::new(
-            self.node.help_symbol.clone(),
-            self.node.help_text.clone(),
-            token,
-            true,
-            &[&self.node.name],
-            &[],
-        )
-    }
-
-    fn matches(&self, _parser: &Parser, token: Token) -> bool {
-        self.node.name.starts_with(token.text)
+    fn matches(&self, parser: &Parser, token: Token) -> bool {
+        parser.matches_name(&self.node, token.text)
     }
 }
 
@@ -391,11 +1057,21 @@ impl ParameterNode {
         kind: ParameterKind,
         required: bool,
     ) -> Self {
-        let help_symbol = if repeatable {
-            String::from("<") + name + ">..."
-        } else {
-            String::from("<") + name + ">"
+        // Flags are shown by name, the value of a named parameter is
+        // shown as a placeholder and simple parameters are shown as
+        // either a placeholder or optional, based on requiredness.
+        let mut help_symbol = match kind {
+            ParameterKind::Flag => name.to_string(),
+            ParameterKind::Named => String::from("<") + name.trim_start_matches('-') + ">",
+            ParameterKind::Simple if required => String::from("<") + name + ">",
+            ParameterKind::Simple => String::from("[") + name + "]",
         };
+        if repeatable {
+            help_symbol.push_str("...");
+        }
+        if kind == ParameterKind::Flag && !required {
+            help_symbol = String::from("[") + &help_symbol + "]";
+        }
         let default_help_text = match kind {
             ParameterKind::Flag => "Flag",
             ParameterKind::Named | ParameterKind::Simple => "Parameter",
@@ -404,34 +1080,121 @@ impl ParameterNode {
         ParameterNode {
             node: TreeNode {
                 name: name.to_string(),
+                aliases: vec![],
                 help_symbol: help_symbol,
                 help_text: help_text,
                 hidden: hidden,
+                deprecated: false,
                 priority: priority,
                 repeat_marker: repeat_marker,
                 repeatable: repeatable,
                 successors: successors,
+                successor_index: OnceCell::new(),
             },
             kind: kind,
             required: required,
+            choices: vec![],
+            path_validation: PathValidation::None,
+            value_type: ValueType::String,
+            greedy: false,
+            negatable: false,
+            negates: None,
+            experimental: false,
+            slot: None,
+            default_value: None,
+            context_key: None,
+            min_occurs: 0,
+            max_occurs: None,
+            requires: vec![],
+            conflicts_with: vec![],
+            completer: None,
+            list_separator: None,
+            value_optional: false,
+        }
+    }
+
+    /// The most times that this repeatable parameter may be given.
+    ///
+    /// A parameter which isn't repeatable has no limit here, as it
+    /// isn't acceptable to the parser once it has been given.
+    pub fn occurrence_limit(&self) -> Option<usize> {
+        if self.node.repeatable {
+            self.max_occurs
+        } else {
+            None
+        }
+    }
+
+    /// The choice given by `value`, when it is a prefix of only one
+    /// of the `choices`. Otherwise, `value` is kept as it is, so
+    /// that `Parser::verify` can report it.
+    fn expand_choice(&self, parser: &Parser, value: &str) -> String {
+        if let Some(choice) = self.choices.iter().find(|c| parser.is_choice(c, value)) {
+            return choice.clone();
+        }
+        let mut matches = self.choices.iter().filter(|c| parser.matches_prefix(c, value));
+        match (matches.next(), matches.next()) {
+            (Some(choice), None) => choice.clone(),
+            _ => value.to_string(),
         }
     }
 }
 
 impl NodeOps for ParameterNode {
-    /// Record this parameter value.
+    fn tree_node(&self) -> Option<&TreeNode> {
+        Some(&self.node)
+    }
+
+    /// Record this parameter value. Quoted values are recorded
+    /// without their quotes, so that an empty quoted string is
+    /// recorded as an empty value.
+    ///
+    /// A repeatable flag records the number of times it has
+    /// been given. A value with a `list_separator` is also
+    /// recorded as the values between the separators.
     fn accept<'text>(&self, parser: &mut Parser<'text>, token: Token, _node_ref: &Rc<Node>) {
-        if self.node.repeatable {
-            unimplemented!();
+        if let Some(ref slot) = self.slot {
+            parser.parameters.insert(slot.clone(), self.node.name.clone());
+        }
+        if let Some(ref negated) = self.negates {
+            parser.parameters.insert(negated.clone(), "false".to_string());
+        } else if self.negatable {
+            parser.parameters.insert(self.node.name.clone(), "true".to_string());
+        } else if self.node.repeatable && self.kind == ParameterKind::Flag {
+            let count = parser
+                .parameters
+                .get(&self.node.name)
+                .and_then(|c| c.parse::<usize>().ok())
+                .unwrap_or(0);
+            parser.parameters.insert(self.node.name.clone(), (count + 1).to_string());
+        } else if let Some(separator) = self.list_separator {
+            let values = token
+                .value()
+                .split(separator)
+                .filter(|v| !v.is_empty())
+                .map(|v| self.expand_choice(parser, v))
+                .collect::<Vec<_>>();
+            let value = values.join(&separator.to_string());
+            parser.repeated_values.entry(self.node.name.clone()).or_default().extend(values);
+            parser.parameters.insert(self.node.name.clone(), value);
+        } else if self.node.repeatable {
+            let value = self.expand_choice(parser, &token.value());
+            parser
+                .repeated_values
+                .entry(self.node.name.clone())
+                .or_default()
+                .push(value.clone());
+            parser.parameters.insert(self.node.name.clone(), value);
         } else {
-            parser.parameters.insert(
-                self.node.name.clone(),
-                token.text.to_string(),
-            );
+            let value = self.expand_choice(parser, &token.value());
+            parser.parameters.insert(self.node.name.clone(), value);
         }
     }
 
     fn acceptable(&self, parser: &Parser, node_ref: &Rc<Node>) -> bool {
+        if self.experimental && !parser.experimental {
+            return false;
+        }
         if self.node.repeatable {
             return true;
         }
@@ -444,35 +1207,45 @@ impl NodeOps for ParameterNode {
 
     /// By default named and simple parameters complete only to the token
     /// being input while flag parameters complete to the name of the flag.
+    ///
+    /// Named and simple parameters with `choices` or a `completer`
+    /// complete to those choices and the values that it provides.
     fn complete<'text>(&self, token: Option<Token<'text>>) -> Completion<'text> {
         match self.kind {
             ParameterKind::Named | ParameterKind::Simple => {
+                let mut values = self.choices.clone();
+                if let Some(ref completer) = self.completer {
+                    values.extend(completer(token.map_or("", |t| t.text)));
+                }
+                let values = values.iter().map(|v| v.as_str()).collect::<Vec<_>>();
                 Completion::new(
                     self.node.help_symbol.clone(),
                     self.node.help_text.clone(),
                     token,
                     true,
-                    &[],
-                    &[],
-                )
-            }
-            ParameterKind::Flag => {
-                Completion::new(
-                    self.node.help_symbol.clone(),
-                    self.node.help_text.clone(),
-                    token,
-                    true,
-                    &[&self.node.name],
+                    &values,
                     &[],
                 )
             }
+            ParameterKind::Flag => complete_name(&self.node, token),
         }
     }
 
-    fn matches(&self, _parser: &Parser, token: Token) -> bool {
+    fn matches(&self, parser: &Parser, token: Token) -> bool {
         match self.kind {
+            ParameterKind::Named | ParameterKind::Simple if !self.choices.is_empty() => {
+                let value = token.value();
+                let matches =
+                    |value: &str| self.choices.iter().any(|c| parser.matches_prefix(c, value));
+                match self.list_separator {
+                    Some(separator) => {
+                        value.split(separator).filter(|v| !v.is_empty()).all(matches)
+                    }
+                    None => matches(&value),
+                }
+            }
             ParameterKind::Named | ParameterKind::Simple => true,
-            ParameterKind::Flag => self.node.name.starts_with(token.text),
+            ParameterKind::Flag => parser.matches_name(&self.node, token.text),
         }
     }
 }
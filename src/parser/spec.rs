@@ -0,0 +1,183 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+use serde_json;
+
+use super::builder::{Command, CommandTree, Parameter};
+use super::constants::ParameterKind;
+
+/// Errors that may happen when loading a [`CommandTree`] with
+/// `CommandTree::from_json`.
+///
+/// [`CommandTree`]: struct.CommandTree.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpecError {
+    /// The document isn't valid JSON, or a field has a value
+    /// of the wrong type. This holds the message describing why.
+    Json(String),
+    /// An object has a field which isn't part of the schema.
+    /// This holds the name of the field.
+    UnknownField(String),
+    /// A parameter has a `kind` which isn't one of `flag`,
+    /// `named` or `simple`. This holds the name of the parameter
+    /// and the kind.
+    InvalidKind(String, String),
+    /// A string contains an escape sequence, so it can't be
+    /// borrowed from the document. This holds the string.
+    EscapedString(String),
+}
+
+impl Error for SpecError {}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SpecError::Json(ref message) => write!(f, "Invalid command document: {}", message),
+            SpecError::UnknownField(ref field) => write!(f, "Unknown field '{}'.", field),
+            SpecError::InvalidKind(ref name, ref kind) => {
+                write!(f, "The parameter '{}' has an invalid kind '{}'.", name, kind)
+            }
+            SpecError::EscapedString(ref text) => {
+                write!(f, "The string '{}' contains an escape sequence.", text)
+            }
+        }
+    }
+}
+
+impl From<serde_json::Error> for SpecError {
+    fn from(error: serde_json::Error) -> SpecError {
+        let message = error.to_string();
+        let field = message.strip_prefix("unknown field `").and_then(|m| m.split('`').next());
+        match field {
+            Some(field) => SpecError::UnknownField(field.to_string()),
+            None => SpecError::Json(message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocumentSpec<'a> {
+    #[serde(borrow, default)]
+    commands: Vec<CommandSpec<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandSpec<'a> {
+    #[serde(borrow)]
+    name: Text<'a>,
+    #[serde(borrow, default)]
+    help: Option<Text<'a>>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    priority: Option<i32>,
+    #[serde(borrow, default)]
+    parameters: Vec<ParameterSpec<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParameterSpec<'a> {
+    #[serde(borrow)]
+    name: Text<'a>,
+    #[serde(borrow, default)]
+    help: Option<Text<'a>>,
+    #[serde(borrow, default)]
+    kind: Option<Text<'a>>,
+    #[serde(default)]
+    required: bool,
+    #[serde(borrow, default)]
+    aliases: Vec<Text<'a>>,
+}
+
+/// A string in the document, which is borrowed unless it
+/// contains an escape sequence.
+#[derive(Deserialize)]
+struct Text<'a>(#[serde(borrow)] Cow<'a, str>);
+
+impl<'a> Text<'a> {
+    fn borrowed(self) -> Result<&'a str, SpecError> {
+        match self.0 {
+            Cow::Borrowed(text) => Ok(text),
+            Cow::Owned(text) => Err(SpecError::EscapedString(text)),
+        }
+    }
+}
+
+impl<'a> CommandTree<'a> {
+    /// Create a new `CommandTree` from a JSON document describing
+    /// its commands.
+    ///
+    /// The document is an object with a `commands` array. Each
+    /// command has a `name` and may have `help` text, be `hidden`,
+    /// have a `priority` and have `parameters`. Each parameter has
+    /// a `name` and may have `help` text, a `kind` of `flag`,
+    /// `named` or `simple` (the default), be `required` and have
+    /// `aliases`.
+    ///
+    /// Fields which aren't part of this schema are rejected with
+    /// `SpecError::UnknownField`. Strings are borrowed from the
+    /// document, so a string with an escape sequence is rejected
+    /// with `SpecError::EscapedString`.
+    ///
+    /// This requires the `serde` feature.
+    ///
+    /// ```
+    /// use commands::parser::CommandTree;
+    ///
+    /// let tree = CommandTree::from_json(r#"{"commands": [
+    ///     {"name": "show", "help": "Show an item.", "parameters": [
+    ///         {"name": "--all", "kind": "flag"}
+    ///     ]}
+    /// ]}"#).unwrap();
+    /// assert!(tree.try_finalize().is_ok());
+    /// ```
+    pub fn from_json(json: &'a str) -> Result<Self, SpecError> {
+        let document: DocumentSpec<'a> = serde_json::from_str(json)?;
+        let mut tree = CommandTree::new();
+        for spec in document.commands {
+            tree.command(build_command(spec)?);
+        }
+        Ok(tree)
+    }
+}
+
+fn build_command(spec: CommandSpec) -> Result<Command, SpecError> {
+    let mut command = Command::new(spec.name.borrowed()?).hidden(spec.hidden);
+    if let Some(help) = spec.help {
+        command = command.help(help.borrowed()?);
+    }
+    if let Some(priority) = spec.priority {
+        command = command.priority(priority);
+    }
+    for spec in spec.parameters {
+        let name = spec.name.borrowed()?;
+        let kind = match spec.kind.map(|k| k.0) {
+            None => ParameterKind::Simple,
+            Some(kind) => match &*kind {
+                "flag" => ParameterKind::Flag,
+                "named" => ParameterKind::Named,
+                "simple" => ParameterKind::Simple,
+                _ => return Err(SpecError::InvalidKind(name.to_string(), kind.into_owned())),
+            },
+        };
+        let mut parameter = Parameter::new(name).kind(kind).required(spec.required);
+        if let Some(help) = spec.help {
+            parameter = parameter.help(help.borrowed()?);
+        }
+        for alias in spec.aliases {
+            parameter = parameter.alias(alias.borrowed()?);
+        }
+        command = command.parameter(parameter);
+    }
+    Ok(command)
+}
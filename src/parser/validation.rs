@@ -0,0 +1,141 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::path::Path;
+
+/// Indicate how the value of a parameter which is a path
+/// should be validated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PathValidation {
+    /// The path is not validated.
+    None,
+    /// The path must exist.
+    MustExist,
+    /// The path must exist and be a file.
+    MustBeFile,
+    /// The path must exist and be a directory.
+    MustBeDir,
+}
+
+impl PathValidation {
+    /// Check a `path` against this validation using the given
+    /// [`FileSystem`].
+    ///
+    /// [`FileSystem`]: trait.FileSystem.html
+    pub fn check(&self, file_system: &dyn FileSystem, path: &Path) -> bool {
+        match *self {
+            PathValidation::None => true,
+            PathValidation::MustExist => file_system.exists(path),
+            PathValidation::MustBeFile => file_system.is_file(path),
+            PathValidation::MustBeDir => file_system.is_dir(path),
+        }
+    }
+}
+
+/// The type of the value of a parameter, which `verify` checks
+/// that the value can be parsed as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ValueType {
+    /// Any value.
+    String,
+    /// A signed integer, like `-12`.
+    Integer,
+    /// A floating point number, like `1.5`.
+    Float,
+    /// A boolean, given as `true`, `false`, `yes`, `no`, `on`
+    /// or `off`, without regard to case.
+    Boolean,
+}
+
+impl ValueType {
+    /// Parse `text` as a value of this type, giving `None` if it
+    /// isn't valid.
+    ///
+    /// ```
+    /// use commands::parser::{TypedValue, ValueType};
+    ///
+    /// assert_eq!(ValueType::Integer.parse("42"), Some(TypedValue::Integer(42)));
+    /// assert_eq!(ValueType::Boolean.parse("Off"), Some(TypedValue::Boolean(false)));
+    /// assert_eq!(ValueType::Float.parse("many"), None);
+    /// ```
+    pub fn parse(&self, text: &str) -> Option<TypedValue> {
+        match *self {
+            ValueType::String => Some(TypedValue::String(text.to_string())),
+            ValueType::Integer => text.parse().ok().map(TypedValue::Integer),
+            ValueType::Float => text.parse().ok().map(TypedValue::Float),
+            ValueType::Boolean => {
+                match text.to_ascii_lowercase().as_str() {
+                    "true" | "yes" | "on" => Some(TypedValue::Boolean(true)),
+                    "false" | "no" | "off" => Some(TypedValue::Boolean(false)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// A description of a value of this type, for use in messages.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ValueType::String => "a string",
+            ValueType::Integer => "an integer",
+            ValueType::Float => "a number",
+            ValueType::Boolean => "a boolean",
+        }
+    }
+}
+
+/// The value of a parameter, parsed according to its [`ValueType`].
+///
+/// [`ValueType`]: enum.ValueType.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedValue {
+    /// A `ValueType::String` value.
+    String(String),
+    /// A `ValueType::Integer` value.
+    Integer(i64),
+    /// A `ValueType::Float` value.
+    Float(f64),
+    /// A `ValueType::Boolean` value.
+    Boolean(bool),
+}
+
+/// The filesystem operations used to validate path parameters.
+///
+/// The parser does not access the filesystem unless it has been
+/// given an implementation of this trait. This keeps the parser
+/// free of I/O by default and allows a mock filesystem to be
+/// used when testing.
+pub trait FileSystem {
+    /// Does the `path` exist?
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Is the `path` an existing file?
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Is the `path` an existing directory?
+    fn is_dir(&self, path: &Path) -> bool;
+}
+
+/// A [`FileSystem`] which uses the actual filesystem.
+///
+/// [`FileSystem`]: trait.FileSystem.html
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+}
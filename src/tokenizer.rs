@@ -61,15 +61,18 @@
 //! [tokens]: struct.Token.html
 //! [whitespace or a word]: enum.TokenType.html
 
+use std::borrow::Cow;
 use std::fmt;
 use std::error::Error;
+use std::ops::Range;
 
 /// A position within a body of text.
 ///
-/// The `SourceOffset` tracks 2 different ways of locating the
+/// The `SourceOffset` tracks 3 different ways of locating the
 /// position:
 ///
 /// * The index of the character within the body of text.
+/// * The offset in bytes of the character within the body of text.
 /// * The column and line number of the character.
 ///
 /// The `SourceOffset` is typically used as a pair of offsets
@@ -81,6 +84,9 @@ use std::error::Error;
 pub struct SourceOffset {
     /// The index of this character within the body of text.
     pub char: usize,
+    /// The offset in bytes of this character within the body of
+    /// text, so that the text can be sliced at this position.
+    pub byte: usize,
     /// The line number on which this character may be found.
     pub line: usize,
     /// The column on which this character may be found.
@@ -89,9 +95,13 @@ pub struct SourceOffset {
 
 impl SourceOffset {
     /// Construct a `SourceOffset`.
-    pub fn new(char: usize, line: usize, column: usize) -> SourceOffset {
+    ///
+    /// The `byte` offset is given after the `char` index. Before it
+    /// was tracked, this took just the `char`, `line` and `column`.
+    pub fn new(char: usize, byte: usize, line: usize, column: usize) -> SourceOffset {
         SourceOffset {
             char: char,
+            byte: byte,
             line: line,
             column: column,
         }
@@ -118,39 +128,66 @@ impl SourceLocation {
 }
 
 /// Errors
-#[derive(Clone, Debug)]
+///
+/// Each offset is the offset in bytes within the body of text of
+/// the character which caused the error.
+#[derive(Clone, Debug, PartialEq)]
 pub enum TokenizerError {
-    /// Character not allowed here
-    CharacterNotAllowedHere(usize),
+    /// Invalid escape sequence, at the offset of the escaped character
+    InvalidEscape(usize, char),
 
     /// Special not yet implemented
     SpecialNotYetImplemented(usize),
 
-    /// Escaping backslash at end of input
-    EscapingBackslashAtEndOfInput,
+    /// Escaping backslash at end of input, at the offset of the backslash
+    EscapingBackslashAtEndOfInput(usize),
+
+    /// Unclosed double quote at end of input, at the offset of the quote
+    UnclosedDoubleQuote(usize),
 
-    /// Unclosed double quote at end of input
-    UnclosedDoubleQuote,
+    /// Unclosed single quote at end of input, at the offset of the quote
+    UnclosedSingleQuote(usize),
 
-    /// Unclosed single quote at end of input
-    UnclosedSingleQuote,
+    /// Here document without a terminating line
+    UnterminatedHereDocument,
 }
 
 impl Error for TokenizerError {
     fn description(&self) -> &str {
         match *self {
-            TokenizerError::CharacterNotAllowedHere(_) => "Character not allowed here",
+            TokenizerError::InvalidEscape(_, _) => "Invalid escape sequence",
             TokenizerError::SpecialNotYetImplemented(_) => "Special not yet implemented",
-            TokenizerError::EscapingBackslashAtEndOfInput => "Escaping backlash at end of input",
-            TokenizerError::UnclosedDoubleQuote => "Unclosed double quote at end of input",
-            TokenizerError::UnclosedSingleQuote => "Unclosed single quote at end of input",
+            TokenizerError::EscapingBackslashAtEndOfInput(_) => {
+                "Escaping backlash at end of input"
+            }
+            TokenizerError::UnclosedDoubleQuote(_) => "Unclosed double quote at end of input",
+            TokenizerError::UnclosedSingleQuote(_) => "Unclosed single quote at end of input",
+            TokenizerError::UnterminatedHereDocument => "Here document without a terminating line",
+        }
+    }
+}
+
+impl TokenizerError {
+    /// The offset in bytes within the body of text at which the
+    /// error occurred, if it occurred at a specific character.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            TokenizerError::InvalidEscape(offset, _) |
+            TokenizerError::SpecialNotYetImplemented(offset) |
+            TokenizerError::EscapingBackslashAtEndOfInput(offset) |
+            TokenizerError::UnclosedDoubleQuote(offset) |
+            TokenizerError::UnclosedSingleQuote(offset) => Some(offset),
+            TokenizerError::UnterminatedHereDocument => None,
         }
     }
 }
 
 impl fmt::Display for TokenizerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.description().fmt(f)
+        match *self {
+            TokenizerError::InvalidEscape(_, c) => write!(f, "Invalid escape sequence `\\{}`", c),
+            _ => self.description().fmt(f),
+        }
     }
 }
 
@@ -164,6 +201,18 @@ pub enum TokenType {
     Word,
 }
 
+/// The kind of a token, which distinguishes quoted words from
+/// other words, as is useful for syntax highlighting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    /// The token is a word which isn't quoted.
+    Word,
+    /// The token is a word in single or double quotes.
+    QuotedWord,
+    /// The token represents whitespace and not a word.
+    Whitespace,
+}
+
 /// A token from a body of text.
 ///
 /// The lifetime parameter `'text` refers to the lifetime
@@ -188,6 +237,164 @@ impl<'text> Token<'text> {
             location: location,
         }
     }
+
+    /// The text of the token without the surrounding quotes, if
+    /// the token is a quoted string.
+    ///
+    /// An empty quoted string results in an empty string.
+    ///
+    /// ```
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// if let Ok(tokens) = tokenize(r#"a "b c" ''"#) {
+    ///     assert_eq!(tokens[0].unquoted(), "a");
+    ///     assert_eq!(tokens[2].unquoted(), "b c");
+    ///     assert_eq!(tokens[4].unquoted(), "");
+    /// }
+    /// ```
+    pub fn unquoted(&self) -> &'text str {
+        let text = self.text;
+        if self.is_quoted() {
+            &text[1..text.len() - 1]
+        } else {
+            text
+        }
+    }
+
+    /// The range of bytes within the body of text that this token
+    /// was taken from, so that `&text[token.span()]` is the text
+    /// of the token.
+    ///
+    /// ```
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// let text = "café crème";
+    /// if let Ok(tokens) = tokenize(text) {
+    ///     assert_eq!(&text[tokens[2].span()], "crème");
+    /// }
+    /// ```
+    pub fn span(&self) -> Range<usize> {
+        let start = self.location.start.byte;
+        start..start + self.text.len()
+    }
+
+    /// The value of the token: its text without the surrounding
+    /// quotes, and with escape sequences interpreted.
+    ///
+    /// Within double quotes, `\"`, `\\`, `\n` and `\t` give a double
+    /// quote, a backslash, a newline and a tab. Outside of quotes,
+    /// a backslash followed by whitespace gives that whitespace, so
+    /// that `foo\ bar` is the single word `foo bar`. Any other escape
+    /// sequence which the tokenizer accepts, like `\q`, is kept
+    /// literally, as is everything within single quotes.
+    ///
+    /// Some escape sequences are rejected by the tokenizer with
+    /// `TokenizerError::InvalidEscape`, so they never reach a token:
+    /// outside of quotes, a backslash followed by anything other
+    /// than an alphanumeric character or whitespace, like `\!`, and
+    /// within quotes, a backslash followed by whitespace. A
+    /// backslash at the end of the input is also rejected, with
+    /// `TokenizerError::EscapingBackslashAtEndOfInput`.
+    ///
+    /// ```
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// if let Ok(tokens) = tokenize(r#"foo\ bar "say \"hi\"\n""#) {
+    ///     assert_eq!(tokens[0].value(), "foo bar");
+    ///     assert_eq!(tokens[2].value(), "say \"hi\"\n");
+    /// }
+    /// ```
+    pub fn value(&self) -> Cow<'text, str> {
+        let text = self.unquoted();
+        let double_quoted = self.is_quoted() && self.text.starts_with('"');
+        if !text.contains('\\') || self.is_quoted() && !double_quoted {
+            return Cow::Borrowed(text);
+        }
+        let mut value = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('"') if double_quoted => value.push('"'),
+                Some('\\') if double_quoted => value.push('\\'),
+                Some('n') if double_quoted => value.push('\n'),
+                Some('t') if double_quoted => value.push('\t'),
+                Some(c) if !double_quoted && c.is_whitespace() => value.push(c),
+                Some(c) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                None => value.push('\\'),
+            }
+        }
+        Cow::Owned(value)
+    }
+
+    /// The kind of the token.
+    ///
+    /// ```
+    /// use commands::tokenizer::{tokenize, TokenKind};
+    ///
+    /// if let Ok(tokens) = tokenize(r#"echo "hi""#) {
+    ///     assert_eq!(tokens[0].kind(), TokenKind::Word);
+    ///     assert_eq!(tokens[1].kind(), TokenKind::Whitespace);
+    ///     assert_eq!(tokens[2].kind(), TokenKind::QuotedWord);
+    /// }
+    /// ```
+    pub fn kind(&self) -> TokenKind {
+        match self.token_type {
+            TokenType::Whitespace => TokenKind::Whitespace,
+            TokenType::Word if self.is_quoted() => TokenKind::QuotedWord,
+            TokenType::Word => TokenKind::Word,
+        }
+    }
+
+    /// Whether or not the token is a quoted string.
+    ///
+    /// This allows a quoted word like `"show"` to be told apart
+    /// from the unquoted `show`, although both have the same
+    /// `unquoted` text.
+    ///
+    /// ```
+    /// use commands::tokenizer::tokenize;
+    ///
+    /// if let Ok(tokens) = tokenize(r#"show "show""#) {
+    ///     assert!(!tokens[0].is_quoted());
+    ///     assert!(tokens[2].is_quoted());
+    /// }
+    /// ```
+    pub fn is_quoted(&self) -> bool {
+        let text = self.text;
+        text.len() >= 2 &&
+            (text.starts_with('"') && text.ends_with('"') ||
+                 text.starts_with('\'') && text.ends_with('\''))
+    }
+}
+
+/// Options which control how a body of text is tokenized.
+///
+/// The default options are used by [`tokenize`].
+///
+/// [`tokenize`]: fn.tokenize.html
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenizerOptions {
+    /// When `true`, an unquoted `=` is emitted as a token of its
+    /// own, so that both `name = value` and `name=value` result in
+    /// the tokens `name`, `=` and `value`. The parser consumes these
+    /// tokens as separators after `Parser::set_equals_separator`.
+    pub equals_separator: bool,
+    /// When `true`, `<<END` at the end of a line starts a here
+    /// document. The lines which follow, up to a line which is
+    /// just `END`, are emitted as a single word token.
+    pub here_documents: bool,
+    /// When `true`, whitespace tokens are left out of the result,
+    /// so that only words are emitted. Whitespace is needed to
+    /// reconstruct the text from the tokens, as the parser does
+    /// for greedy parameters, so this defaults to `false`.
+    pub drop_whitespace: bool,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -205,22 +412,28 @@ enum State {
 
 struct Tokenizer<'text> {
     text: &'text str,
+    options: TokenizerOptions,
     state: State,
     token_type: Option<TokenType>,
     token_start: usize,
     token_end: usize,
+    skip_to: usize,
     tokens: Vec<Token<'text>>,
+    position: SourceOffset,
 }
 
 impl<'text> Tokenizer<'text> {
-    fn new(text: &'text str) -> Tokenizer {
+    fn new(text: &'text str, options: TokenizerOptions) -> Tokenizer {
         Tokenizer {
             text: text,
+            options: options,
             state: State::Initial,
             token_type: None,
             token_start: 0,
             token_end: 0,
+            skip_to: 0,
             tokens: vec![],
+            position: SourceOffset::new(0, 0, 0, 0),
         }
     }
 
@@ -232,10 +445,11 @@ impl<'text> Tokenizer<'text> {
     }
 
     fn reduce(&mut self) {
-        let token_text = &self.text[self.token_start..self.token_end + 1];
+        let token_end = self.token_end + self.char_len(self.token_end);
+        let token_text = &self.text[self.token_start..token_end];
         let loc = SourceLocation::new(
-            SourceOffset::new(self.token_start, 0, self.token_start),
-            SourceOffset::new(self.token_end, 0, self.token_end),
+            self.source_offset(self.token_start),
+            self.source_offset(self.token_end),
         );
         self.tokens.push(Token::new(
             token_text,
@@ -262,15 +476,93 @@ impl<'text> Tokenizer<'text> {
         }
     }
 
+    /// The length in bytes of the character at the byte `offset`.
+    fn char_len(&self, offset: usize) -> usize {
+        self.text[offset..].chars().next().map_or(0, char::len_utf8)
+    }
+
+    /// The location of the character at the byte `offset`.
+    ///
+    /// Locations are found in order as the text is tokenized, so
+    /// this continues from the last location found rather than
+    /// counting from the start of the text each time.
+    fn source_offset(&mut self, offset: usize) -> SourceOffset {
+        if offset < self.position.byte {
+            self.position = SourceOffset::new(0, 0, 0, 0);
+        }
+        for c in self.text[self.position.byte..offset].chars() {
+            self.position.char += 1;
+            self.position.byte += c.len_utf8();
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 0;
+            } else {
+                self.position.column += 1;
+            }
+        }
+        self.position
+    }
+
     fn special(&mut self, offset: usize) {
         self.shift(offset, State::Special);
         self.reduce();
     }
 
-    fn initial(&mut self, offset: usize, c: char) {
+    /// Emit the body of a here document starting at `offset` as a
+    /// single token, returning whether there was a here document.
+    fn here_document(&mut self, start: usize) -> Result<bool, TokenizerError> {
+        let rest = &self.text[start..];
+        if !rest.starts_with("<<") {
+            return Ok(false);
+        }
+        let delimiter_len = rest[2..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - 2);
+        let delimiter = &rest[2..2 + delimiter_len];
+        let after = &rest[2 + delimiter_len..];
+        let line_end = if after.starts_with("\r\n") {
+            2
+        } else if after.starts_with('\n') {
+            1
+        } else {
+            return Ok(false);
+        };
+        if delimiter.is_empty() {
+            return Ok(false);
+        }
+        let body_start = start + 2 + delimiter_len + line_end;
+        let mut line_start = body_start;
+        loop {
+            if line_start > self.text.len() {
+                return Err(TokenizerError::UnterminatedHereDocument);
+            }
+            let line_len = self.text[line_start..]
+                .find('\n')
+                .unwrap_or(self.text.len() - line_start);
+            let line = &self.text[line_start..line_start + line_len];
+            if line.trim_end_matches('\r') == delimiter {
+                break;
+            }
+            line_start += line_len + 1;
+        }
+        let body = &self.text[body_start..line_start];
+        let body = body.trim_end_matches('\n').trim_end_matches('\r');
+        let body_end = body_start + body.char_indices().last().map_or(0, |(i, _)| i);
+        let loc = SourceLocation::new(self.source_offset(body_start), self.source_offset(body_end));
+        self.skip_to = line_start + delimiter.len();
+        self.tokens.push(Token::new(body, TokenType::Word, loc));
+        Ok(true)
+    }
+
+    fn initial(&mut self, offset: usize, c: char) -> Result<(), TokenizerError> {
+        if c == '<' && self.options.here_documents && self.here_document(offset)? {
+            return Ok(());
+        }
         if c.is_whitespace() {
             self.shift(offset, State::Whitespace);
-        } else if c == ';' || c == '?' || c == '|' {
+        } else if c == ';' || c == '?' || c == '|' ||
+                   (c == '=' && self.options.equals_separator)
+        {
             self.special(offset);
         } else if c == '"' {
             self.shift(offset, State::Doublequote);
@@ -282,25 +574,31 @@ impl<'text> Tokenizer<'text> {
         } else {
             self.shift(offset, State::Word);
         }
+        Ok(())
     }
 
     fn tokenize(&mut self) -> Result<(), TokenizerError> {
-        for (offset, c) in self.text.chars().enumerate() {
+        for (offset, c) in self.text.char_indices() {
+            if offset < self.skip_to {
+                continue;
+            }
             match self.state {
-                State::Initial => self.initial(offset, c),
+                State::Initial => self.initial(offset, c)?,
                 State::Whitespace => {
                     if c.is_whitespace() {
                         self.shift(offset, State::Whitespace);
                     } else {
                         self.reduce();
-                        self.initial(offset, c);
+                        self.initial(offset, c)?;
                     };
                 }
                 State::Word => {
                     if c.is_whitespace() {
                         self.reduce();
                         self.shift(offset, State::Whitespace);
-                    } else if c == ';' || c == '|' {
+                    } else if c == ';' || c == '|' ||
+                               (c == '=' && self.options.equals_separator)
+                    {
                         self.reduce();
                         self.special(offset);
                     } else if c == '"' {
@@ -320,7 +618,7 @@ impl<'text> Tokenizer<'text> {
                     if c.is_alphanumeric() || c.is_whitespace() {
                         self.shift(offset, State::Word);
                     } else {
-                        return Err(TokenizerError::CharacterNotAllowedHere(offset));
+                        return Err(TokenizerError::InvalidEscape(offset, c));
                     };
                 }
                 State::Doublequote => {
@@ -337,7 +635,7 @@ impl<'text> Tokenizer<'text> {
                     if !c.is_whitespace() {
                         self.shift(offset, State::Doublequote);
                     } else {
-                        return Err(TokenizerError::CharacterNotAllowedHere(offset));
+                        return Err(TokenizerError::InvalidEscape(offset, c));
                     };
                 }
                 State::Singlequote => {
@@ -354,7 +652,7 @@ impl<'text> Tokenizer<'text> {
                     if !c.is_whitespace() {
                         self.shift(offset, State::Singlequote);
                     } else {
-                        return Err(TokenizerError::CharacterNotAllowedHere(offset));
+                        return Err(TokenizerError::InvalidEscape(offset, c));
                     };
                 }
                 State::Special => {
@@ -367,12 +665,14 @@ impl<'text> Tokenizer<'text> {
         match self.state {
             State::Initial => {}
             State::Word | State::Whitespace => self.reduce(),
-            State::WordBackslash => return Err(TokenizerError::EscapingBackslashAtEndOfInput),
-            State::Doublequote => return Err(TokenizerError::UnclosedDoubleQuote),
-            State::Singlequote => return Err(TokenizerError::UnclosedSingleQuote),
+            State::WordBackslash => {
+                return Err(TokenizerError::EscapingBackslashAtEndOfInput(self.token_end))
+            }
+            State::Doublequote => return Err(TokenizerError::UnclosedDoubleQuote(self.token_start)),
+            State::Singlequote => return Err(TokenizerError::UnclosedSingleQuote(self.token_start)),
             State::DoublequoteBackslash |
             State::SinglequoteBackslash => {
-                return Err(TokenizerError::EscapingBackslashAtEndOfInput)
+                return Err(TokenizerError::EscapingBackslashAtEndOfInput(self.token_end))
             }
             State::Special => {
                 return Err(TokenizerError::SpecialNotYetImplemented(
@@ -386,14 +686,80 @@ impl<'text> Tokenizer<'text> {
 }
 
 /// Tokenize a body of text.
+///
+/// This doesn't copy the text: each [`Token`] borrows its text
+/// from `text`. A copy is only made by `Token::value` when the
+/// token contains escape sequences which must be interpreted.
+///
+/// [`Token`]: struct.Token.html
 pub fn tokenize(text: &str) -> Result<Vec<Token>, TokenizerError> {
-    let mut tokenizer = Tokenizer::new(text);
+    tokenize_with_options(text, TokenizerOptions::default())
+}
+
+/// Tokenize a body of text using the given [`TokenizerOptions`].
+///
+/// ```
+/// use commands::tokenizer::{tokenize_with_options, TokenizerOptions};
+///
+/// let options = TokenizerOptions {
+///     equals_separator: true,
+///     ..Default::default()
+/// };
+/// if let Ok(tokens) = tokenize_with_options("color=red", options) {
+///     assert_eq!(tokens.len(), 3);
+///     assert_eq!(tokens[1].text, "=");
+/// }
+/// ```
+///
+/// [`TokenizerOptions`]: struct.TokenizerOptions.html
+pub fn tokenize_with_options<'text>(
+    text: &'text str,
+    options: TokenizerOptions,
+) -> Result<Vec<Token<'text>>, TokenizerError> {
+    let mut tokenizer = Tokenizer::new(text, options);
     match tokenizer.tokenize() {
+        Ok(_) if options.drop_whitespace => {
+            tokenizer.tokens.retain(|t| t.token_type != TokenType::Whitespace);
+            Ok(tokenizer.tokens)
+        }
         Ok(_) => Ok(tokenizer.tokens),
         Err(error) => Err(error),
     }
 }
 
+/// Quote `text`, if needed, so that it is tokenized as a single
+/// word whose [`value`] is `text`.
+///
+/// Text which contains whitespace, quotes or characters with a
+/// special meaning to the tokenizer is given in double quotes,
+/// with any double quotes and backslashes within it escaped.
+///
+/// ```
+/// use commands::tokenizer::quote;
+///
+/// assert_eq!(quote("plain"), "plain");
+/// assert_eq!(quote("two words"), "\"two words\"");
+/// assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+/// ```
+///
+/// [`value`]: struct.Token.html#method.value
+pub fn quote(text: &str) -> String {
+    let needs_quotes = text.is_empty() ||
+        text.chars().any(|c| c.is_whitespace() || "\"'\\;?|=<".contains(c));
+    if !needs_quotes {
+        return text.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -403,8 +769,8 @@ mod test {
             text,
             token_type,
             SourceLocation::new(
-                SourceOffset::new(start, 0, start),
-                SourceOffset::new(end, 0, end),
+                SourceOffset::new(start, start, 0, start),
+                SourceOffset::new(end, end, 0, end),
             ),
         )
     }
@@ -483,14 +849,22 @@ mod test {
     }
 
     #[test]
-    fn character_not_allowed_here() {
+    fn invalid_escape() {
         match tokenize(r#"ab \!"#) {
-            Err(TokenizerError::CharacterNotAllowedHere(_)) => {}
+            Err(TokenizerError::InvalidEscape(4, '!')) => {}
             _ => panic!(),
         };
 
         match tokenize(r#"ab "\ ab"#) {
-            Err(TokenizerError::CharacterNotAllowedHere(_)) => {}
+            Err(TokenizerError::InvalidEscape(5, ' ')) => {}
+            _ => panic!(),
+        };
+
+        match tokenize("é \\€") {
+            Err(error) => {
+                assert_eq!(error, TokenizerError::InvalidEscape(4, '€'));
+                assert_eq!(error.to_string(), "Invalid escape sequence `\\€`");
+            }
             _ => panic!(),
         };
     }
@@ -501,7 +875,7 @@ mod test {
     #[should_panic]
     fn escaping_backslash_at_end_of_input() {
         match tokenize(r#"ab \"#) {
-            Err(TokenizerError::EscapingBackslashAtEndOfInput) => panic!(),
+            Err(TokenizerError::EscapingBackslashAtEndOfInput(3)) => panic!(),
             _ => {}
         }
     }
@@ -510,7 +884,7 @@ mod test {
     #[should_panic]
     fn unclosed_double_quote_at_end_of_input() {
         match tokenize(r#"ab ""#) {
-            Err(TokenizerError::UnclosedDoubleQuote) => panic!(),
+            Err(TokenizerError::UnclosedDoubleQuote(3)) => panic!(),
             _ => {}
         }
     }
@@ -519,8 +893,246 @@ mod test {
     #[should_panic]
     fn escaped_double_quote_at_end_of_input() {
         match tokenize(r#"ab "\"#) {
-            Err(TokenizerError::EscapingBackslashAtEndOfInput) => panic!(),
+            Err(TokenizerError::EscapingBackslashAtEndOfInput(4)) => panic!(),
             _ => {}
         }
     }
+
+    #[test]
+    fn equals_separator() {
+        let options = TokenizerOptions {
+            equals_separator: true,
+            ..Default::default()
+        };
+        match tokenize_with_options("a=b", options) {
+            Ok(ts) => {
+                assert_eq!(ts.len(), 3);
+                assert_eq!(ts[0], mk_token("a", TokenType::Word, 0, 0));
+                assert_eq!(ts[1], mk_token("=", TokenType::Word, 1, 1));
+                assert_eq!(ts[2], mk_token("b", TokenType::Word, 2, 2));
+            }
+            _ => panic!(),
+        };
+
+        match tokenize("a=b") {
+            Ok(ts) => {
+                assert_eq!(ts.len(), 1);
+                assert_eq!(ts[0], mk_token("a=b", TokenType::Word, 0, 2));
+            }
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn quoted_equals_is_not_separator() {
+        let options = TokenizerOptions {
+            equals_separator: true,
+            ..Default::default()
+        };
+        match tokenize_with_options(r#"a "=" 'b=c'"#, options) {
+            Ok(ts) => {
+                assert_eq!(ts.len(), 5);
+                assert_eq!(ts[2], mk_token(r#""=""#, TokenType::Word, 2, 4));
+                assert_eq!(ts[4], mk_token("'b=c'", TokenType::Word, 6, 10));
+            }
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn empty_quoted_string() {
+        match tokenize(r#"a """#) {
+            Ok(ts) => {
+                assert_eq!(ts.len(), 3);
+                assert_eq!(ts[2], mk_token(r#""""#, TokenType::Word, 2, 3));
+                assert_eq!(ts[2].unquoted(), "");
+                assert!(ts[2].is_quoted());
+            }
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn quotes_adjacent_to_text() {
+        let ts = tokenize(r#"a"b"c"#).unwrap();
+        assert_eq!(ts.len(), 3);
+        assert_eq!(ts[0], mk_token("a", TokenType::Word, 0, 0));
+        assert_eq!(ts[1], mk_token(r#""b""#, TokenType::Word, 1, 3));
+        assert_eq!(ts[2], mk_token("c", TokenType::Word, 4, 4));
+        assert!(!ts[0].is_quoted());
+        assert!(ts[1].is_quoted());
+        assert_eq!(ts[1].unquoted(), "b");
+    }
+
+    #[test]
+    fn whitespace_within_quotes() {
+        let ts = tokenize("echo \"  hello \t world  \"").unwrap();
+        assert_eq!(ts.len(), 3);
+        assert_eq!(ts[2].unquoted(), "  hello \t world  ");
+        assert!(ts[2].is_quoted());
+    }
+
+    #[test]
+    fn here_document() {
+        let options = TokenizerOptions {
+            here_documents: true,
+            ..Default::default()
+        };
+        let text = "cat <<END\nfirst line\nsecond line\nEND\nls";
+        let tokens = tokenize_with_options(text, options).unwrap();
+        assert_eq!(
+            tokens.iter().map(|t| t.text).collect::<Vec<_>>(),
+            vec!["cat", " ", "first line\nsecond line", "\n", "ls"]
+        );
+        assert_eq!(tokens[2].token_type, TokenType::Word);
+        assert_eq!(tokens[2].location.start.char, 10);
+
+        match tokenize_with_options("cat <<END\nfirst line\n", options) {
+            Err(TokenizerError::UnterminatedHereDocument) => {}
+            _ => panic!(),
+        }
+
+        // Here documents are only recognized when enabled.
+        let tokens = tokenize("cat <<END").unwrap();
+        assert_eq!(tokens[2].text, "<<END");
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        let texts = [
+            "plain",
+            "",
+            "two words",
+            "a;b",
+            "say \"hi\"",
+            "it's",
+            "back\\slash",
+            "'\"\\",
+            "line\nbreak",
+        ];
+        for text in &texts {
+            let quoted = quote(text);
+            let tokens = tokenize(&quoted).unwrap();
+            assert_eq!(tokens.len(), 1);
+            assert_eq!(tokens[0].value(), *text);
+        }
+    }
+
+    #[test]
+    fn escapes_within_double_quotes() {
+        let ts = tokenize(r#""a\"b\\c\nd\te""#).unwrap();
+        assert_eq!(ts.len(), 1);
+        assert_eq!(ts[0].value(), "a\"b\\c\nd\te");
+
+        // Unknown escapes are kept literally.
+        let ts = tokenize(r#""\q""#).unwrap();
+        assert_eq!(ts[0].value(), "\\q");
+
+        // Single quoted text is kept literally.
+        let ts = tokenize(r#"'a\nb'"#).unwrap();
+        assert_eq!(ts[0].value(), "a\\nb");
+
+        // Escaped whitespace is rejected within either kind of quotes.
+        match tokenize(r#"'a\ b'"#) {
+            Err(TokenizerError::InvalidEscape(3, ' ')) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn escapes_outside_of_quotes() {
+        let ts = tokenize(r#"foo\ bar baz"#).unwrap();
+        assert_eq!(ts.len(), 3);
+        assert_eq!(ts[0].text, r#"foo\ bar"#);
+        assert_eq!(ts[0].value(), "foo bar");
+
+        let ts = tokenize(r#"a\q"#).unwrap();
+        assert_eq!(ts[0].value(), "a\\q");
+
+        match tokenize("foo\\") {
+            Err(TokenizerError::EscapingBackslashAtEndOfInput(_)) => {}
+            _ => panic!(),
+        }
+        match tokenize("\"foo\\") {
+            Err(TokenizerError::EscapingBackslashAtEndOfInput(_)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn spans_with_multibyte_characters() {
+        let text = "café \"crème brûlée\"\nnaïve";
+        let ts = tokenize(text).unwrap();
+        let words = ts.iter().filter(|t| t.token_type == TokenType::Word).collect::<Vec<_>>();
+        assert_eq!(words.len(), 3);
+        for token in &ts {
+            assert_eq!(&text[token.span()], token.text);
+        }
+        assert_eq!(words[0].span(), 0..5);
+        assert_eq!(words[1].span(), 6..23);
+        assert_eq!(words[1].location.start, SourceOffset::new(5, 6, 0, 5));
+        assert_eq!(words[1].location.end, SourceOffset::new(18, 22, 0, 18));
+        assert_eq!(words[2].span(), 24..30);
+        assert_eq!(words[2].location.start, SourceOffset::new(20, 24, 1, 0));
+        assert_eq!(words[2].location.end, SourceOffset::new(24, 29, 1, 4));
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        assert_eq!(tokenize(r#"a "b c"#).unwrap_err(), TokenizerError::UnclosedDoubleQuote(2));
+        assert_eq!(tokenize("a 'b c").unwrap_err(), TokenizerError::UnclosedSingleQuote(2));
+        assert_eq!(tokenize("é 'b").unwrap_err().offset(), Some(3));
+    }
+
+    #[test]
+    fn token_kinds() {
+        let kinds = |tokens: Vec<Token>| tokens.iter().map(|t| t.kind()).collect::<Vec<_>>();
+        let text = r#"show   "a b""#;
+        assert_eq!(
+            kinds(tokenize(text).unwrap()),
+            vec![TokenKind::Word, TokenKind::Whitespace, TokenKind::QuotedWord]
+        );
+        assert_eq!(tokenize(text).unwrap()[1].text, "   ");
+
+        let options = TokenizerOptions {
+            drop_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            kinds(tokenize_with_options(text, options).unwrap()),
+            vec![TokenKind::Word, TokenKind::QuotedWord]
+        );
+    }
+
+    #[test]
+    fn tokens_borrow_from_text() {
+        let text = String::from(r#"show "a b" c\ d"#);
+        let range = text.as_bytes().as_ptr_range();
+        for token in tokenize(&text).unwrap() {
+            assert!(range.contains(&token.text.as_ptr()));
+            assert_eq!(&text[token.span()], token.text);
+        }
+        let tokens = tokenize(&text).unwrap();
+        assert!(matches!(tokens[2].value(), Cow::Borrowed("a b")));
+        assert!(matches!(tokens[4].value(), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn locations_of_long_input() {
+        let line = "é word \"two words\"\n";
+        let text = line.repeat(5000);
+        let tokens = tokenize(&text).unwrap();
+        let last = tokens.last().unwrap();
+        assert_eq!(last.text, "\n");
+        let expected = SourceOffset::new(
+            text.chars().count() - 1,
+            text.len() - 1,
+            4999,
+            line.chars().count() - 1,
+        );
+        assert_eq!(last.location.start, expected);
+        for token in &tokens {
+            assert_eq!(&text[token.span()], token.text);
+        }
+    }
 }
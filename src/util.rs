@@ -40,6 +40,105 @@ pub fn longest_common_prefix<'s>(strings: &'s [&str]) -> &'s str {
     &strings[0][..len]
 }
 
+/// Prefix matching
+///
+/// Determine whether `prefix` is a prefix of `text`.
+///
+/// When the `unicode-segmentation` feature is enabled, the
+/// prefix must also end on a grapheme cluster boundary within
+/// `text`, so that a prefix can not split a character which is
+/// made up of a combining sequence.
+///
+/// ```
+/// use commands::util::is_prefix;
+///
+/// assert!(is_prefix("show", "sh"));
+/// assert!(!is_prefix("show", "set"));
+/// ```
+pub fn is_prefix(text: &str, prefix: &str) -> bool {
+    text.starts_with(prefix) && is_grapheme_boundary(text, prefix.len())
+}
+
+/// Prefix matching, ignoring ASCII case
+///
+/// Determine whether `prefix` is a prefix of `text` when ASCII
+/// letters are compared without regard to their case. This is
+/// otherwise the same as [`is_prefix`].
+///
+/// ```
+/// use commands::util::is_prefix_ignoring_case;
+///
+/// assert!(is_prefix_ignoring_case("show", "SH"));
+/// assert!(!is_prefix_ignoring_case("show", "SET"));
+/// ```
+///
+/// [`is_prefix`]: fn.is_prefix.html
+pub fn is_prefix_ignoring_case(text: &str, prefix: &str) -> bool {
+    text.is_char_boundary(prefix.len()) &&
+        text[..prefix.len()].eq_ignore_ascii_case(prefix) &&
+        is_grapheme_boundary(text, prefix.len())
+}
+
+/// Fuzzy matching
+///
+/// Score how well `pattern` matches `text` as a subsequence,
+/// giving `None` when the characters of `pattern` don't all
+/// appear within `text` in the same order.
+///
+/// Each character of `pattern` is matched with its first
+/// occurrence in `text` after the previous match. A matched
+/// character scores 1, plus 2 when it directly follows the
+/// previous match, plus 3 when it starts `text` or follows a
+/// `-`, `_` or space. Higher scores are better matches, and the
+/// score depends only on the two strings.
+///
+/// ```
+/// use commands::util::fuzzy_score;
+///
+/// assert_eq!(fuzzy_score("shw", "show"), Some(8));
+/// assert!(fuzzy_score("show", "show") > fuzzy_score("shw", "show"));
+/// assert_eq!(fuzzy_score("wsh", "show"), None);
+/// ```
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<u32> {
+    let mut score = 0;
+    let mut chars = text.chars().enumerate();
+    let mut previous_char = None;
+    let mut previous_match = None;
+    for p in pattern.chars() {
+        loop {
+            let (index, c) = chars.next()?;
+            let starts_word = matches!(previous_char, None | Some('-') | Some('_') | Some(' '));
+            previous_char = Some(c);
+            if c == p {
+                score += 1;
+                if previous_match.map(|m| m + 1) == Some(index) {
+                    score += 2;
+                }
+                if starts_word {
+                    score += 3;
+                }
+                previous_match = Some(index);
+                break;
+            }
+        }
+    }
+    Some(score)
+}
+
+#[cfg(feature = "unicode-segmentation")]
+fn is_grapheme_boundary(text: &str, offset: usize) -> bool {
+    use unicode_segmentation::GraphemeCursor;
+
+    GraphemeCursor::new(offset, text.len(), true)
+        .is_boundary(text, 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(feature = "unicode-segmentation"))]
+fn is_grapheme_boundary(_text: &str, _offset: usize) -> bool {
+    true
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -69,4 +168,31 @@ mod test {
     fn valid_is_shortest_lcp() {
         assert_eq!(longest_common_prefix(&["aba", "ab", "abc"]), "ab");
     }
+
+    #[test]
+    fn prefix() {
+        assert!(is_prefix("show", ""));
+        assert!(is_prefix("show", "sh"));
+        assert!(is_prefix("show", "show"));
+        assert!(!is_prefix("show", "shows"));
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("", "show"), Some(0));
+        assert_eq!(fuzzy_score("shw", "show"), Some(8));
+        assert_eq!(fuzzy_score("sw", "show"), Some(5));
+        assert_eq!(fuzzy_score("ss", "set-size"), Some(8));
+        assert_eq!(fuzzy_score("shows", "show"), None);
+        assert!(fuzzy_score("sh", "show") > fuzzy_score("sh", "ash"));
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn prefix_on_grapheme_boundary() {
+        // "cafe\u{301}" ends with 'e' followed by a combining accent.
+        assert!(is_prefix("cafe\u{301}", "caf"));
+        assert!(!is_prefix("cafe\u{301}", "cafe"));
+        assert!(is_prefix("cafe\u{301}", "cafe\u{301}"));
+    }
 }